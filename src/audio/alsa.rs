@@ -0,0 +1,142 @@
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr, CString};
+use std::io;
+use std::ptr;
+use std::time::Duration;
+
+use super::Sink;
+
+const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+const SND_PCM_FORMAT_S16_LE: c_int = 2;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+#[repr(C)]
+struct snd_pcm_t {
+    _private: [u8; 0],
+}
+
+#[link(name = "asound")]
+extern "C" {
+    fn snd_pcm_open(
+        pcm: *mut *mut snd_pcm_t,
+        name: *const c_char,
+        stream: c_int,
+        mode: c_int,
+    ) -> c_int;
+    fn snd_pcm_set_params(
+        pcm: *mut snd_pcm_t,
+        format: c_int,
+        access: c_int,
+        channels: c_uint,
+        rate: c_uint,
+        soft_resample: c_int,
+        latency: c_uint,
+    ) -> c_int;
+    fn snd_pcm_writei(pcm: *mut snd_pcm_t, buffer: *const c_void, size: c_ulong) -> c_long;
+    fn snd_pcm_recover(pcm: *mut snd_pcm_t, err: c_int, silent: c_int) -> c_int;
+    fn snd_pcm_delay(pcm: *mut snd_pcm_t, delay: *mut c_long) -> c_int;
+    fn snd_pcm_drain(pcm: *mut snd_pcm_t) -> c_int;
+    fn snd_pcm_close(pcm: *mut snd_pcm_t) -> c_int;
+    fn snd_strerror(errnum: c_int) -> *const c_char;
+}
+
+/// An error if ALSA gave back an error code.
+fn check(code: c_int) -> io::Result<()> {
+    if code >= 0 {
+        return Ok(());
+    }
+    let message = unsafe { CStr::from_ptr(snd_strerror(code)) };
+    Err(io::Error::other(message.to_string_lossy()))
+}
+
+/// Plays on a sound card through ALSA, as 16 bit samples.
+pub struct Alsa {
+    device: String,
+    /// How much is buffered on the sound card.
+    buffer: Duration,
+    pcm: *mut snd_pcm_t,
+    channels: usize,
+    /// Frames written so far.
+    written: u64,
+}
+
+impl Alsa {
+    /// Nothing is opened until the format is known.
+    pub fn new(device: &str, buffer: Duration) -> Self {
+        Self {
+            device: device.into(),
+            buffer,
+            pcm: ptr::null_mut(),
+            channels: 2,
+            written: 0,
+        }
+    }
+}
+
+impl Sink for Alsa {
+    fn open(&mut self, channels: u16, sample_rate: u32) -> io::Result<()> {
+        let name = CString::new(self.device.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        unsafe {
+            check(snd_pcm_open(
+                &mut self.pcm,
+                name.as_ptr(),
+                SND_PCM_STREAM_PLAYBACK,
+                0,
+            ))
+            .map_err(|e| io::Error::other(format!("Couldn't open '{}': {e}", self.device)))?;
+            check(snd_pcm_set_params(
+                self.pcm,
+                SND_PCM_FORMAT_S16_LE,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                channels as c_uint,
+                sample_rate,
+                1,
+                self.buffer.as_micros() as c_uint,
+            ))?;
+        }
+        self.channels = channels as usize;
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let samples: Vec<i16> = samples
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect();
+        let mut frames = &samples[..];
+        while !frames.is_empty() {
+            let count = (frames.len() / self.channels) as c_ulong;
+            let written = unsafe { snd_pcm_writei(self.pcm, frames.as_ptr().cast(), count) };
+            if written < 0 {
+                // underruns and suspends are recovered from, playing on
+                unsafe { check(snd_pcm_recover(self.pcm, written as c_int, 1))? }
+                continue;
+            }
+            frames = &frames[written as usize * self.channels..];
+            self.written += written as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        unsafe { check(snd_pcm_drain(self.pcm)) }
+    }
+
+    /// What's been written but is still waiting in the buffer hasn't been heard.
+    fn played(&mut self) -> Option<u64> {
+        let mut delay = 0;
+        if self.pcm.is_null() || unsafe { snd_pcm_delay(self.pcm, &mut delay) } < 0 {
+            // after an underrun everything has been
+            return Some(self.written);
+        }
+        Some(self.written.saturating_sub(delay.max(0) as u64))
+    }
+}
+
+impl Drop for Alsa {
+    fn drop(&mut self) {
+        if !self.pcm.is_null() {
+            unsafe { snd_pcm_close(self.pcm) };
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(all(feature = "alsa", target_os = "linux"))]
+mod alsa;
+mod stream;
+
+pub use stream::Stream;
+
+#[allow(dead_code, non_upper_case_globals)]
+const ECoInit: u8 = 1;
+#[allow(dead_code, non_upper_case_globals)]
+const EEnumerator: u8 = 2;
+#[allow(dead_code, non_upper_case_globals)]
+const EDevice: u8 = 3;
+#[allow(dead_code, non_upper_case_globals)]
+const ECreateAudioClient: u8 = 4;
+
+extern "C" {
+    fn initialize() -> u8;
+    fn enable_debug();
+    fn platform_supported() -> bool;
+}
+
+pub struct AudioDevice;
+
+impl AudioDevice {
+    pub fn new() -> Result<Self, &'static str> {
+        if unsafe { initialize() } != 0 {
+            return Err("Audio device could not be initialized");
+        }
+        Ok(Self)
+    }
+
+    pub fn enable_debug_mode() {
+        unsafe { enable_debug() };
+    }
+
+    pub fn supports() -> bool {
+        unsafe { platform_supported() }
+    }
+}
+
+/// Where audio goes as it's rendered: a sound card, a file, or nowhere.
+pub trait Sink {
+    /// Called once before anything is written, when the format is known.
+    fn open(&mut self, _channels: u16, _sample_rate: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Plays interleaved samples, waiting until there's room for them.
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Waits until everything written has been played.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Frames heard so far, for sinks that keep time of their own like sound cards
+    /// do. Others are timed by the stream's clock.
+    fn played(&mut self) -> Option<u64> {
+        None
+    }
+}
+
+/// What playing in real time is timed by.
+pub trait Clock {
+    /// Time since the clock started.
+    fn now(&self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The system's own clock.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A sink that throws the samples away, counting them.
+#[derive(Debug, Default)]
+pub struct Null {
+    pub samples: usize,
+}
+
+impl Sink for Null {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.samples += samples.len();
+        Ok(())
+    }
+}
+
+/// The sink that plays on the sound card called `device`, or the default one,
+/// buffering `lookahead` of audio. `null` plays nowhere.
+#[cfg_attr(
+    not(all(feature = "alsa", target_os = "linux")),
+    allow(unused_variables)
+)]
+pub fn device(device: Option<&str>, lookahead: Duration) -> io::Result<Box<dyn Sink>> {
+    match device {
+        Some("null") => Ok(Box::new(Null::default())),
+        #[cfg(all(feature = "alsa", target_os = "linux"))]
+        device => Ok(Box::new(alsa::Alsa::new(
+            device.unwrap_or("default"),
+            lookahead,
+        ))),
+        #[cfg(not(all(feature = "alsa", target_os = "linux")))]
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Playing audio needs the 'alsa' feature, on Linux",
+        )),
+    }
+}
@@ -0,0 +1,298 @@
+use std::io;
+use std::time::Duration;
+
+use super::{Clock, Sink};
+use crate::synth::Renderer;
+use crate::timeline::Timeline;
+
+/// Frames rendered at once while streaming, so that writes aren't too small.
+const BLOCK: usize = 4096;
+
+/// Audio played or written out while the program is still making it.
+pub struct Stream {
+    sink: Box<dyn Sink>,
+    /// Made once the program has set the sample rate and the channels.
+    renderer: Option<Renderer>,
+    /// Set when playing in real time rather than as fast as the program runs.
+    live: Option<Live>,
+}
+
+/// Keeps what's rendered a little ahead of what's heard, so that the program
+/// runs only as far ahead of playback as it needs to.
+struct Live {
+    clock: Box<dyn Clock>,
+    lookahead: Duration,
+    /// When the first frame was written, by the clock.
+    start: Option<Duration>,
+}
+
+impl Stream {
+    /// Writes the audio as soon as it's been made.
+    pub fn new(sink: Box<dyn Sink>) -> Self {
+        Self {
+            sink,
+            renderer: None,
+            live: None,
+        }
+    }
+
+    /// Plays the audio in real time, timed by the sink if it keeps time and by
+    /// `clock` otherwise, up to `lookahead` ahead of what's been heard.
+    pub fn live(sink: Box<dyn Sink>, clock: Box<dyn Clock>, lookahead: Duration) -> Self {
+        let live = Live {
+            clock,
+            lookahead,
+            start: None,
+        };
+        Self {
+            sink,
+            renderer: None,
+            live: Some(live),
+        }
+    }
+
+    fn renderer(&mut self, timeline: &Timeline) -> io::Result<&mut Renderer> {
+        if self.renderer.is_none() {
+            self.sink.open(timeline.channels, timeline.sample_rate)?;
+        }
+        Ok(self.renderer.get_or_insert_with(|| Renderer::new(timeline)))
+    }
+
+    /// Frames heard so far, once playing in real time.
+    fn played(&mut self, sample_rate: u32) -> Option<u64> {
+        let live = self.live.as_ref()?;
+        let played = self.sink.played().unwrap_or_else(|| {
+            let elapsed = live.start.map_or(Duration::ZERO, |s| live.clock.now() - s);
+            (elapsed.as_secs_f64() * sample_rate as f64) as u64
+        });
+        Some(played)
+    }
+
+    /// Frames that can be rendered before getting further than the lookahead
+    /// ahead of what's been heard, which is any number of them unless live.
+    fn room(&mut self, position: usize, sample_rate: u32) -> usize {
+        let Some(played) = self.played(sample_rate) else {
+            return usize::MAX;
+        };
+        let lookahead = self.live.as_ref().unwrap().lookahead.as_secs_f64();
+        // rendering takes a block at a time
+        let lookahead = ((lookahead * sample_rate as f64) as usize).max(BLOCK);
+        (played as usize + lookahead).saturating_sub(position)
+    }
+
+    /// Sleeps while `frames` are played.
+    fn wait(&mut self, frames: usize, sample_rate: u32) {
+        if let Some(live) = &mut self.live {
+            let duration = Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+            live.clock.sleep(duration);
+        }
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        if let Some(live) = &mut self.live {
+            live.start.get_or_insert_with(|| live.clock.now());
+        }
+        self.sink.write(samples)
+    }
+
+    /// Plays up to frame `until`. Playing live, it waits until the lookahead leaves
+    /// room for a block, or for what's left if that's less.
+    fn play(&mut self, timeline: &Timeline, until: usize) -> io::Result<()> {
+        let rate = timeline.sample_rate;
+        loop {
+            let position = self.renderer(timeline)?.position();
+            let block = until.saturating_sub(position).min(BLOCK);
+            if block == 0 {
+                return Ok(());
+            }
+            let room = self.room(position, rate);
+            if room < block {
+                self.wait(block - room, rate);
+                continue;
+            }
+            let end = until.min(position.saturating_add(room));
+            let samples = self.renderer(timeline)?.render(timeline, end);
+            self.write(&samples)?;
+        }
+    }
+
+    /// Plays what's been played before `time`, in whole notes, which is where the
+    /// first voice that's still playing is at. Nothing can be played before it.
+    pub fn advance(&mut self, timeline: &Timeline, time: f32) -> io::Result<()> {
+        let rate = timeline.sample_rate;
+        // a beat earlier, as swing and humanize move notes by up to a beat
+        let time = ((time * 4.0).floor() - 1.0).max(0.0) / 4.0;
+        let until = (timeline.seconds(time) * rate as f32) as usize;
+        if until < self.renderer(timeline)?.position() + BLOCK {
+            return Ok(());
+        }
+        self.play(timeline, until)
+    }
+
+    /// Drops what's been played from the timeline, see `Renderer::forget`.
+    pub fn forget(&mut self, timeline: &mut Timeline, playing: &[usize]) -> Vec<usize> {
+        match &mut self.renderer {
+            Some(renderer) => renderer.forget(timeline, playing),
+            None => vec![],
+        }
+    }
+
+    /// Plays the rest of the timeline once the program is over, and waits until
+    /// it's been heard.
+    pub fn finish(mut self, timeline: &Timeline) -> io::Result<()> {
+        let rate = timeline.sample_rate;
+        let end = self.renderer(timeline)?.end(timeline);
+        self.play(timeline, end)?;
+        self.sink.finish()?;
+        let position = self.renderer(timeline)?.position() as u64;
+        while let Some(played) = self.played(rate).filter(|p| *p < position) {
+            self.wait((position - played) as usize, rate);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Null;
+    use crate::effects::Effects;
+    use crate::synth::{self, Envelope, Instrument, Waveform};
+    use crate::timeline::{Timing, Tone};
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    /// Lets the test see what the stream wrote to a sink it owns.
+    impl<S: Sink> Sink for Rc<RefCell<S>> {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.borrow_mut().write(samples)
+        }
+    }
+
+    /// A clock whose time only passes while it's slept on.
+    struct Fake(Rc<Cell<Duration>>);
+
+    impl Clock for Fake {
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    /// Counts what's written like `Null`, keeping track of how far ahead of what's
+    /// been heard by `now` it gets.
+    struct Recorder {
+        null: Null,
+        now: Rc<Cell<Duration>>,
+        /// When the first frame was written.
+        start: Option<Duration>,
+        /// The most frames that were written but not heard yet.
+        ahead: f64,
+    }
+
+    impl Recorder {
+        /// Frames of stereo heard so far.
+        fn heard(&self) -> f64 {
+            let start = self.start.unwrap_or_default();
+            (self.now.get() - start).as_secs_f64() * synth::SAMPLE_RATE as f64
+        }
+    }
+
+    impl Sink for Recorder {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.start.get_or_insert(self.now.get());
+            self.null.write(samples)?;
+            let written = (self.null.samples / 2) as f64;
+            self.ahead = self.ahead.max(written - self.heard());
+            Ok(())
+        }
+    }
+
+    /// A few seconds of quarter notes, at 60 beats per minute.
+    fn timeline() -> Timeline {
+        let mut timeline = Timeline::new();
+        let instrument = Instrument::Synth {
+            waveform: Waveform::Sine,
+            envelope: Envelope {
+                attack: 0.01,
+                decay: 0.1,
+                sustain: 0.8,
+                release: 0.2,
+            },
+        };
+        let timing = Timing {
+            swing: 0.5,
+            humanize: 0.0,
+        };
+        for key in [60, 62, 64, 65, 67, 69, 71, 72] {
+            let tone = Tone {
+                key,
+                velocity: 80,
+                length: 0.25,
+                offset: 0.0,
+            };
+            let voice = &mut timeline.voices[0];
+            voice.push(
+                vec![tone],
+                0.25,
+                instrument.clone(),
+                timing,
+                None,
+                Effects::default(),
+            );
+        }
+        timeline
+    }
+
+    #[test]
+    fn null_counts_what_is_written() {
+        let timeline = timeline();
+        let null = Rc::new(RefCell::new(Null::default()));
+        let mut stream = Stream::new(Box::new(null.clone()));
+        for time in [0.5, 1.0, 1.5] {
+            stream.advance(&timeline, time).unwrap();
+        }
+        assert!(null.borrow().samples > 0);
+        stream.finish(&timeline).unwrap();
+        assert_eq!(null.borrow().samples, synth::render(&timeline).len());
+    }
+
+    #[test]
+    fn live_stays_within_the_lookahead() {
+        let timeline = timeline();
+        let now = Rc::new(Cell::new(Duration::ZERO));
+        let recorder = Rc::new(RefCell::new(Recorder {
+            null: Null::default(),
+            now: now.clone(),
+            start: None,
+            ahead: 0.0,
+        }));
+        let lookahead = Duration::from_millis(500);
+        let clock = Box::new(Fake(now.clone()));
+        let mut stream = Stream::live(Box::new(recorder.clone()), clock, lookahead);
+        for step in 1..=16 {
+            stream.advance(&timeline, step as f32 / 8.0).unwrap();
+        }
+        stream.finish(&timeline).unwrap();
+
+        let recorder = recorder.borrow();
+        let rate = synth::SAMPLE_RATE as f64;
+        let lookahead = (lookahead.as_secs_f64() * rate).max(BLOCK as f64);
+        assert!(
+            recorder.ahead <= lookahead,
+            "{} frames ahead",
+            recorder.ahead
+        );
+        let samples = synth::render(&timeline).len();
+        assert_eq!(recorder.null.samples, samples);
+        // finishing waits until everything's been heard, and not much longer
+        let frames = (samples / 2) as f64;
+        assert!(recorder.heard() >= frames);
+        assert!(recorder.heard() < frames + BLOCK as f64);
+    }
+}
@@ -1,15 +1,108 @@
-pub struct CompilerArgs {
-    pub run: bool,
-    pub filename: String,
-}
-
-impl Default for CompilerArgs {
-    fn default() -> Self {
-        let run = true;
-        let filename = "".into();
-        Self {
-            run,
-            filename,
-        }
-    }
-}
\ No newline at end of file
+use std::fmt;
+use std::time::Duration;
+
+pub struct CompilerArgs {
+    pub run: bool,
+    pub filename: String,
+    pub output: Option<String>,
+    /// Overrides the `samplerate` of the file.
+    pub sample_rate: Option<u32>,
+    /// Format of the output, instead of the one of its extension.
+    pub format: Option<String>,
+    /// Sound card to play on when running, `null` to play nowhere.
+    pub device: Option<String>,
+    /// How far ahead of what's heard the audio is made when running.
+    pub lookahead: Duration,
+}
+
+impl Default for CompilerArgs {
+    fn default() -> Self {
+        let run = true;
+        let filename = "".into();
+        let output = None;
+        Self {
+            run,
+            filename,
+            output,
+            sample_rate: None,
+            format: None,
+            device: None,
+            lookahead: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    MissingValue(String),
+    Unexpected(String),
+    InvalidValue(String, String),
+    NoOutput,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingValue(s) => write!(f, "'{s}' expects a value"),
+            Self::Unexpected(s) => write!(f, "Unexpected argument '{s}'"),
+            Self::InvalidValue(s, v) => write!(f, "'{v}' isn't a valid value for '{s}'"),
+            Self::NoOutput => write!(f, "'render' needs an output file, given by '-o'"),
+        }
+    }
+}
+
+impl CompilerArgs {
+    /// `musical [run|render] [file] [-o output] [-f format] [-r sample rate] [-d device] [-l lookahead]`
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut out = Self::default();
+        let mut args = args.peekable();
+        match args.peek().map(|s| s.as_str()) {
+            Some("run") => {
+                args.next();
+            }
+            Some("render") => {
+                args.next();
+                out.run = false;
+            }
+            _ => (),
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    out.output = Some(args.next().ok_or(ArgsError::MissingValue(arg))?);
+                }
+                "-f" | "--format" => {
+                    out.format = Some(args.next().ok_or(ArgsError::MissingValue(arg))?);
+                }
+                "-d" | "--device" => {
+                    out.device = Some(args.next().ok_or(ArgsError::MissingValue(arg))?);
+                }
+                "-l" | "--lookahead" => {
+                    let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+                    match value.parse() {
+                        Ok(seconds @ 0.01..=60.0) => {
+                            out.lookahead = Duration::from_secs_f32(seconds)
+                        }
+                        _ => return Err(ArgsError::InvalidValue(arg, value)),
+                    }
+                }
+                "-r" | "--samplerate" => {
+                    let value = args.next().ok_or(ArgsError::MissingValue(arg.clone()))?;
+                    match value.parse() {
+                        Ok(rate @ 8000..=192000) => out.sample_rate = Some(rate),
+                        _ => return Err(ArgsError::InvalidValue(arg, value)),
+                    }
+                }
+                _ if out.filename.is_empty() && !arg.starts_with('-') => out.filename = arg,
+                _ => return Err(ArgsError::Unexpected(arg)),
+            }
+        }
+        if out.filename.is_empty() {
+            out.filename = "test.musical".into();
+        }
+        if !out.run && out.output.is_none() {
+            return Err(ArgsError::NoOutput);
+        }
+        Ok(out)
+    }
+}
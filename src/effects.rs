@@ -0,0 +1,270 @@
+use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4, TAU};
+
+/// Effects applied to the sound of a voice, or to everything for the master effects.
+/// Settings that are 0 turn their effect off.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Effects {
+    /// Cutoff frequencies of the filters.
+    pub lowpass: f32,
+    pub highpass: f32,
+    /// Seconds between echoes.
+    pub delay: f32,
+    /// How loud each echo is compared to the one before it, from 0 to 1.
+    pub feedback: f32,
+    /// How much of the sound is reverb, from 0 to 1.
+    pub reverb: f32,
+    /// Size of the room, from 0 to 1, larger rooms ringing out for longer.
+    pub room: f32,
+    /// Decibels added at the end.
+    pub gain: f32,
+    /// Where the sound is between the left (-1) and the right (1) speaker.
+    pub pan: f32,
+}
+
+impl Effects {
+    /// The effects at `rate` samples per second, ready to process sound.
+    pub fn processor(&self, rate: f32) -> Processor {
+        let feedback = self.feedback.clamp(0.0, 0.95);
+        Processor {
+            lowpass: (self.lowpass > 0.0).then(|| Biquad::lowpass(self.lowpass, rate)),
+            highpass: (self.highpass > 0.0).then(|| Biquad::highpass(self.highpass, rate)),
+            delay: (self.delay > 0.0).then(|| Delay {
+                line: vec![0.0; ((self.delay * rate) as usize).max(1)],
+                index: 0,
+                feedback,
+            }),
+            reverb: (self.reverb > 0.0).then(|| Freeverb::new(self.room, rate)),
+            wet: self.reverb.clamp(0.0, 1.0),
+            gain: 10f32.powf(self.gain / 20.0),
+        }
+    }
+
+    /// Seconds the echoes and the reverb keep sounding for after the sound stops.
+    pub fn tail(&self) -> f32 {
+        let mut tail = 0.0;
+        if self.delay > 0.0 {
+            let feedback = self.feedback.clamp(0.0, 0.95);
+            // until the echoes are 60 dB quieter
+            let echoes = if feedback > 0.0 {
+                (0.001f32.ln() / feedback.ln()).ceil()
+            } else {
+                1.0
+            };
+            tail += self.delay * echoes;
+        }
+        if self.reverb > 0.0 {
+            tail += Freeverb::tail(self.room);
+        }
+        tail
+    }
+}
+
+/// Effects that keep what they need of the sound they've processed, so that it can
+/// be given to them a bit at a time.
+pub struct Processor {
+    lowpass: Option<Biquad>,
+    highpass: Option<Biquad>,
+    delay: Option<Delay>,
+    reverb: Option<Freeverb>,
+    /// How much of the sound is reverb.
+    wet: f32,
+    gain: f32,
+}
+
+impl Processor {
+    /// Applies the effects in order: filters, delay, reverb and gain.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if let Some(filter) = &mut self.lowpass {
+            filter.process(samples);
+        }
+        if let Some(filter) = &mut self.highpass {
+            filter.process(samples);
+        }
+        if let Some(delay) = &mut self.delay {
+            delay.process(samples);
+        }
+        if let Some(reverb) = &mut self.reverb {
+            let wet = self.wet;
+            for sample in samples.iter_mut() {
+                *sample = (1.0 - wet) * *sample + wet * reverb.process(*sample);
+            }
+        }
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|s| *s *= self.gain);
+        }
+    }
+}
+
+/// Constant power gains of the left and right channels at `pan`, both being
+/// `FRAC_1_SQRT_2` in the center.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// A second order filter, see the Audio EQ Cookbook.
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    /// The last two inputs and outputs.
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn lowpass(cutoff: f32, rate: f32) -> Self {
+        let (cos, alpha) = Self::params(cutoff, rate);
+        Self::new(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    fn highpass(cutoff: f32, rate: f32) -> Self {
+        let (cos, alpha) = Self::params(cutoff, rate);
+        Self::new(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            cos,
+            alpha,
+        )
+    }
+
+    fn params(cutoff: f32, rate: f32) -> (f32, f32) {
+        let nyquist = rate / 2.0;
+        let w = TAU * cutoff.clamp(1.0, nyquist * 0.99) / rate;
+        (w.cos(), w.sin() / (2.0 * FRAC_1_SQRT_2))
+    }
+
+    fn new(b: [f32; 3], cos: f32, alpha: f32) -> Self {
+        let a0 = 1.0 + alpha;
+        Self {
+            b: b.map(|b| b / a0),
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let ([x1, x2], [y1, y2]) = (&mut self.x, &mut self.y);
+        for sample in samples.iter_mut() {
+            let x = *sample;
+            let y = self.b[0] * x + self.b[1] * *x1 + self.b[2] * *x2
+                - self.a[0] * *y1
+                - self.a[1] * *y2;
+            (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+            *sample = y;
+        }
+    }
+}
+
+/// Echoes of the sound, each `feedback` times as loud as the one before it.
+struct Delay {
+    line: Vec<f32>,
+    index: usize,
+    feedback: f32,
+}
+
+impl Delay {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let echo = self.line[self.index];
+            self.line[self.index] = *sample + echo * self.feedback;
+            *sample += 0.5 * echo;
+            self.index = (self.index + 1) % self.line.len();
+        }
+    }
+}
+
+/// Jezar's Freeverb, with the delay lengths of the original at 44.1 kHz.
+struct Freeverb {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+    feedback: f32,
+}
+
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+
+impl Freeverb {
+    fn new(room: f32, rate: f32) -> Self {
+        let scale = |length: usize| (length as f32 * rate / 44100.0) as usize;
+        Self {
+            combs: COMBS.iter().map(|&l| Comb::new(scale(l))).collect(),
+            allpasses: ALLPASSES.iter().map(|&l| Allpass::new(scale(l))).collect(),
+            feedback: Self::feedback(room),
+        }
+    }
+
+    /// Seconds it takes to ring out in a room of size `room`.
+    fn tail(room: f32) -> f32 {
+        let longest = COMBS[COMBS.len() - 1] as f32 / 44100.0;
+        // until the combs are 60 dB quieter
+        longest * 0.001f32.ln() / Self::feedback(room).ln()
+    }
+
+    fn feedback(room: f32) -> f32 {
+        0.7 + 0.28 * room.clamp(0.0, 1.0)
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let input = input * 0.015;
+        let mut out = 0.0;
+        for comb in self.combs.iter_mut() {
+            out += comb.process(input, self.feedback);
+        }
+        for allpass in self.allpasses.iter_mut() {
+            out = allpass.process(out);
+        }
+        out * 3.0
+    }
+}
+
+/// A feedback delay whose feedback is low-passed, which makes rooms sound damped.
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filtered: f32,
+}
+
+impl Comb {
+    const DAMP: f32 = 0.2;
+
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+            filtered: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32) -> f32 {
+        let out = self.buffer[self.index];
+        self.filtered = out * (1.0 - Self::DAMP) + self.filtered * Self::DAMP;
+        self.buffer[self.index] = input + self.filtered * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        out
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
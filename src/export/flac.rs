@@ -0,0 +1,537 @@
+use std::io::{self, Write};
+
+/// Frames of each FLAC frame but the last.
+const BLOCK_SIZE: usize = 4096;
+const BITS: u32 = 16;
+
+/// Writes samples as 16 bit FLAC, interleaved if there's more than one channel.
+/// Each channel is predicted by the best of FLAC's fixed polynomials, stereo being
+/// turned into mid and side channels when they're smaller.
+pub fn write(
+    out: &mut impl Write,
+    samples: &[f32],
+    channels: u16,
+    sample_rate: u32,
+) -> io::Result<()> {
+    let samples: Vec<i64> = samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i64)
+        .collect();
+    let channels = channels as usize;
+    let frames = samples.len() / channels;
+
+    out.write_all(b"fLaC")?;
+    let mut info = Bits::default();
+    // the last metadata block, STREAMINFO, and its size
+    info.write(1, 1);
+    info.write(0, 7);
+    info.write(34, 24);
+    info.write(BLOCK_SIZE as u64, 16);
+    info.write(BLOCK_SIZE as u64, 16);
+    // smallest and largest frame sizes, unknown
+    info.write(0, 24);
+    info.write(0, 24);
+    info.write(sample_rate as u64, 20);
+    info.write(channels as u64 - 1, 3);
+    info.write(BITS as u64 - 1, 5);
+    info.write(frames as u64, 36);
+    // MD5 of the samples, unknown
+    for _ in 0..4 {
+        info.write(0, 32);
+    }
+    out.write_all(&info.bytes)?;
+
+    for (number, block) in samples.chunks(BLOCK_SIZE * channels).enumerate() {
+        out.write_all(&frame(block, channels, number as u32))?;
+    }
+    out.flush()
+}
+
+/// A FLAC frame of the interleaved samples of `block`.
+fn frame(block: &[i64], channels: usize, number: u32) -> Vec<u8> {
+    let size = block.len() / channels;
+    let channel =
+        |c: usize| -> Vec<i64> { block.iter().skip(c).step_by(channels).copied().collect() };
+    let (assignment, subframes) = if channels == 2 {
+        let (left, right) = (channel(0), channel(1));
+        let mid: Vec<i64> = left.iter().zip(&right).map(|(l, r)| (l + r) >> 1).collect();
+        let side: Vec<i64> = left.iter().zip(&right).map(|(l, r)| l - r).collect();
+        let [left, right, mid, side] = [
+            subframe(&left, BITS),
+            subframe(&right, BITS),
+            subframe(&mid, BITS),
+            // the side channel takes a bit more
+            subframe(&side, BITS + 1),
+        ];
+        let options = [
+            (0b0001, [left.clone(), right.clone()]),
+            (0b1000, [left, side.clone()]),
+            (0b1001, [side.clone(), right]),
+            (0b1010, [mid, side]),
+        ];
+        let (assignment, subframes) = options
+            .into_iter()
+            .min_by_key(|(_, s)| s[0].len() + s[1].len())
+            .unwrap();
+        (assignment, subframes.to_vec())
+    } else {
+        let subframes = (0..channels).map(|c| subframe(&channel(c), BITS)).collect();
+        (channels as u64 - 1, subframes)
+    };
+
+    let mut bits = Bits::default();
+    bits.write(0b11111111111110, 14);
+    bits.write(0, 1);
+    // fixed block sizes
+    bits.write(0, 1);
+    // the block size comes after the frame number unless it's the usual one
+    bits.write(if size == BLOCK_SIZE { 0b1100 } else { 0b0111 }, 4);
+    // sample rate and bits per sample, the latter being 16
+    bits.write(0, 4);
+    bits.write(assignment, 4);
+    bits.write(0b100, 3);
+    bits.write(0, 1);
+    write_utf8(&mut bits, number);
+    if size != BLOCK_SIZE {
+        bits.write(size as u64 - 1, 16);
+    }
+    let crc = crc8(&bits.bytes);
+    bits.write(crc as u64, 8);
+    for subframe in subframes.iter() {
+        bits.append(subframe);
+    }
+    let mut bytes = bits.finish();
+    let crc = crc16(&bytes);
+    bytes.extend_from_slice(&crc.to_be_bytes());
+    bytes
+}
+
+/// The smallest of a constant, a verbatim and a fixed subframe of `samples`, which
+/// take `bits` bits each.
+fn subframe(samples: &[i64], bits: u32) -> Bits {
+    let mut out = Bits::default();
+    if samples.iter().all(|s| *s == samples[0]) {
+        out.write(0b0000000, 7);
+        out.write(0, 1);
+        out.write_signed(samples[0], bits);
+        return out;
+    }
+    // the order whose residuals are the smallest, usually the cheapest to code
+    let (order, residuals) = (0..=4.min(samples.len()))
+        .map(|order| (order, residuals(samples, order)))
+        .min_by_key(|(_, r)| r.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap();
+    out.write(0b0001000 | order as u64, 7);
+    out.write(0, 1);
+    for sample in samples[..order].iter() {
+        out.write_signed(*sample, bits);
+    }
+    write_residuals(&mut out, &residuals, samples.len(), order);
+    if out.len() < samples.len() * bits as usize + 8 {
+        return out;
+    }
+    let mut out = Bits::default();
+    out.write(0b0000001, 7);
+    out.write(0, 1);
+    for sample in samples {
+        out.write_signed(*sample, bits);
+    }
+    out
+}
+
+/// What's left of `samples` once they're predicted by the fixed polynomial of `order`.
+fn residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    let coefficients: &[i64] = match order {
+        0 => &[],
+        1 => &[1],
+        2 => &[2, -1],
+        3 => &[3, -3, 1],
+        _ => &[4, -6, 4, -1],
+    };
+    (order..samples.len())
+        .map(|i| {
+            let prediction: i64 = coefficients
+                .iter()
+                .enumerate()
+                .map(|(j, c)| c * samples[i - 1 - j])
+                .sum();
+            samples[i] - prediction
+        })
+        .collect()
+}
+
+/// Writes the residuals as Rice codes, split into the number of partitions that
+/// takes the fewest bits, each with its own parameter.
+fn write_residuals(out: &mut Bits, residuals: &[i64], size: usize, order: usize) {
+    // the first partition's warm up samples aren't in `residuals`
+    let partitions = |p: u32| {
+        let length = size >> p;
+        (0..1usize << p).map(move |i| {
+            let start = (i * length).saturating_sub(order);
+            &residuals[start..(i + 1) * length - order]
+        })
+    };
+    let best = (0..=8)
+        .filter(|p| size.is_multiple_of(1 << p) && size >> p >= order.max(1))
+        .map(|p| {
+            let parameters: Vec<u32> = partitions(p).map(|r| rice_parameter(r).0).collect();
+            let cost: usize = partitions(p).map(|r| rice_parameter(r).1).sum();
+            (p, parameters, cost)
+        })
+        .min_by_key(|(_, _, cost)| *cost)
+        .unwrap();
+    let (p, parameters, _) = best;
+    // Rice coding with 4 bit parameters
+    out.write(0, 2);
+    out.write(p as u64, 4);
+    for (partition, k) in partitions(p).zip(parameters) {
+        out.write(k as u64, 4);
+        for residual in partition {
+            let value = zigzag(*residual);
+            let quotient = value >> k;
+            out.write_zeros(quotient);
+            out.write(1, 1);
+            out.write(value & ((1 << k) - 1), k);
+        }
+    }
+}
+
+/// The Rice parameter that codes `residuals` in the fewest bits, with their count.
+fn rice_parameter(residuals: &[i64]) -> (u32, usize) {
+    let cost = |k: u32| {
+        residuals
+            .iter()
+            .map(|r| (zigzag(*r) >> k) as usize + 1 + k as usize)
+            .sum::<usize>()
+    };
+    let mean = residuals.iter().map(|r| zigzag(*r)).sum::<u64>() / residuals.len().max(1) as u64;
+    // the best parameter is close to the log of the mean, 15 means escaped values
+    let guess = (64 - mean.leading_zeros()).min(14);
+    (guess.saturating_sub(1)..=(guess + 1).min(14))
+        .map(|k| (k, cost(k)))
+        .min_by_key(|(_, cost)| *cost)
+        .unwrap()
+}
+
+/// Interleaves positive and negative numbers: 0, -1, 1, -2...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Writes `value` like a character in UTF-8, which FLAC codes frame numbers as.
+fn write_utf8(bits: &mut Bits, value: u32) {
+    if value < 0x80 {
+        bits.write(value as u64, 8);
+        return;
+    }
+    let continuations = match value {
+        0x80..=0x7FF => 1,
+        0x800..=0xFFFF => 2,
+        0x10000..=0x1FFFFF => 3,
+        0x200000..=0x3FFFFFF => 4,
+        _ => 5,
+    };
+    let prefix = 0xFF00u64 >> (continuations + 1) & 0xFF;
+    bits.write(prefix | (value >> (6 * continuations)) as u64, 8);
+    for i in (0..continuations).rev() {
+        bits.write(0x80 | (value >> (6 * i) & 0x3F) as u64, 8);
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                crc << 1 ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Bits written from the most significant one on.
+#[derive(Debug, Clone, Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    /// Bits that don't make a whole byte yet, in the low end.
+    pending: u64,
+    count: u32,
+}
+
+impl Bits {
+    /// Writes the low `count` bits of `value`, `count` being at most 56.
+    fn write(&mut self, value: u64, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.pending = self.pending << count | (value & ((1 << count) - 1));
+        self.count += count;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.bytes.push((self.pending >> self.count) as u8);
+        }
+    }
+
+    /// Writes `value` in two's complement.
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64, count);
+    }
+
+    fn write_zeros(&mut self, mut count: u64) {
+        while count > 0 {
+            let n = count.min(32);
+            self.write(0, n as u32);
+            count -= n;
+        }
+    }
+
+    fn append(&mut self, other: &Bits) {
+        for byte in other.bytes.iter() {
+            self.write(*byte as u64, 8);
+        }
+        self.write(other.pending, other.count);
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.len() * 8 + self.count as usize
+    }
+
+    /// The bytes written, the last one padded with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        let padding = (8 - self.count % 8) % 8;
+        self.write(0, padding);
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    /// Reads bits from the most significant one on, like `Bits` writes them.
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl Reader<'_> {
+        fn read(&mut self, count: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..count {
+                let bit = self.bytes[self.position / 8] >> (7 - self.position % 8) & 1;
+                value = value << 1 | bit as u64;
+                self.position += 1;
+            }
+            value
+        }
+
+        fn read_signed(&mut self, count: u32) -> i64 {
+            ((self.read(count) << (64 - count)) as i64) >> (64 - count)
+        }
+
+        fn byte(&self) -> usize {
+            self.position.div_ceil(8)
+        }
+    }
+
+    /// What a file that `write` wrote holds: its sample rate, its channels, its
+    /// interleaved samples and the types of its subframes. Each frame's CRCs are
+    /// checked on the way.
+    fn decode(bytes: &[u8]) -> (u32, usize, Vec<i64>, Vec<u64>) {
+        assert_eq!(&bytes[..4], b"fLaC");
+        let mut r = Reader {
+            bytes,
+            position: 32,
+        };
+        assert_eq!((r.read(1), r.read(7), r.read(24)), (1, 0, 34));
+        r.read(80);
+        let rate = r.read(20) as u32;
+        let channels = r.read(3) as usize + 1;
+        let bits = r.read(5) as u32 + 1;
+        let frames = r.read(36) as usize;
+        r.read(128);
+        let (mut samples, mut kinds) = (vec![], vec![]);
+        let mut number = 0;
+        while r.byte() < bytes.len() {
+            let start = r.byte();
+            assert_eq!(r.read(16), 0b1111111111111000);
+            let size_code = r.read(4);
+            assert_eq!(r.read(4), 0);
+            let assignment = r.read(4);
+            assert_eq!((r.read(3), r.read(1)), (0b100, 0));
+            // the frame number, coded like UTF-8
+            let first = r.read(8);
+            let continuations = (first as u8).leading_ones().saturating_sub(1);
+            let mut value = first & (0x7F >> continuations);
+            for _ in 0..continuations {
+                value = value << 6 | (r.read(8) & 0x3F);
+            }
+            assert_eq!(value, number);
+            let size = match size_code {
+                0b1100 => BLOCK_SIZE,
+                0b0111 => r.read(16) as usize + 1,
+                _ => panic!("unexpected block size {size_code:#b}"),
+            };
+            let crc = crc8(&bytes[start..r.byte()]);
+            assert_eq!(r.read(8) as u8, crc);
+            let mut decoded = vec![];
+            for channel in 0..channels {
+                let side = matches!((assignment, channel), (0b1000 | 0b1010, 1) | (0b1001, 0));
+                let (kind, subframe) = subframe(&mut r, size, bits + side as u32);
+                kinds.push(kind);
+                decoded.push(subframe);
+            }
+            let end = r.byte();
+            r.position = end * 8;
+            assert_eq!(r.read(16) as u16, crc16(&bytes[start..end]));
+            if channels == 2 {
+                let (a, b) = (&decoded[0], &decoded[1]);
+                let (left, right): (Vec<i64>, Vec<i64>) = match assignment {
+                    0b0001 => (a.clone(), b.clone()),
+                    0b1000 => (a.clone(), a.iter().zip(b).map(|(l, s)| l - s).collect()),
+                    0b1001 => (a.iter().zip(b).map(|(s, r)| s + r).collect(), b.clone()),
+                    _ => a
+                        .iter()
+                        .zip(b)
+                        .map(|(mid, side)| {
+                            let mid = mid << 1 | side & 1;
+                            ((mid + side) >> 1, (mid - side) >> 1)
+                        })
+                        .unzip(),
+                };
+                decoded = vec![left, right];
+            }
+            for i in 0..size {
+                samples.extend(decoded.iter().map(|c| c[i]));
+            }
+            number += 1;
+        }
+        assert_eq!(samples.len(), frames * channels);
+        (rate, channels, samples, kinds)
+    }
+
+    /// A subframe of `size` samples of `bits` bits each, with its type.
+    fn subframe(r: &mut Reader, size: usize, bits: u32) -> (u64, Vec<i64>) {
+        assert_eq!(r.read(1), 0);
+        let kind = r.read(6);
+        // no wasted bits
+        assert_eq!(r.read(1), 0);
+        let samples = match kind {
+            0 => vec![r.read_signed(bits); size],
+            1 => (0..size).map(|_| r.read_signed(bits)).collect(),
+            8..=12 => {
+                let order = kind as usize - 8;
+                let mut samples: Vec<i64> = (0..order).map(|_| r.read_signed(bits)).collect();
+                assert_eq!(r.read(2), 0);
+                let partitions = r.read(4);
+                for partition in 0..1 << partitions {
+                    let k = r.read(4) as u32;
+                    let count = (size >> partitions) - if partition == 0 { order } else { 0 };
+                    for _ in 0..count {
+                        let mut quotient = 0;
+                        while r.read(1) == 0 {
+                            quotient += 1;
+                        }
+                        let value = quotient << k | r.read(k);
+                        let residual = (value >> 1) as i64 ^ -((value & 1) as i64);
+                        let s = |i: usize| samples[samples.len() - i];
+                        let prediction = match order {
+                            0 => 0,
+                            1 => s(1),
+                            2 => 2 * s(1) - s(2),
+                            3 => 3 * s(1) - 3 * s(2) + s(3),
+                            _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+                        };
+                        samples.push(prediction + residual);
+                    }
+                }
+                samples
+            }
+            _ => panic!("unexpected subframe type {kind}"),
+        };
+        (kind, samples)
+    }
+
+    fn quantize(samples: &[f32]) -> Vec<i64> {
+        samples
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i64)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut noise = Random::new(1);
+        let mut samples = vec![];
+        // silence, then noise, which is cheapest verbatim, then a tune and an odd
+        // block at the end
+        for i in 0..3 * BLOCK_SIZE + 1000 {
+            let t = i as f32 / 44100.0;
+            let (left, right) = match i / BLOCK_SIZE {
+                0 => (0.0, 0.0),
+                1 => (noise.float() * 2.0 - 1.0, noise.float() * 2.0 - 1.0),
+                _ => {
+                    let tune = (t * 440.0 * std::f32::consts::TAU).sin() * 0.5;
+                    (
+                        tune,
+                        tune * 0.8 + (t * 660.0 * std::f32::consts::TAU).sin() * 0.1,
+                    )
+                }
+            };
+            samples.extend([left, right]);
+        }
+        let mut bytes = vec![];
+        write(&mut bytes, &samples, 2, 44100).unwrap();
+        let (rate, channels, decoded, kinds) = decode(&bytes);
+        assert_eq!((rate, channels), (44100, 2));
+        assert_eq!(decoded, quantize(&samples));
+        for kind in [0, 1] {
+            assert!(kinds.contains(&kind));
+        }
+        assert!(kinds.iter().any(|k| (8..=12).contains(k)));
+
+        let mono: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let mut bytes = vec![];
+        write(&mut bytes, &mono, 1, 48000).unwrap();
+        let (rate, channels, decoded, _) = decode(&bytes);
+        assert_eq!((rate, channels), (48000, 1));
+        assert_eq!(decoded, quantize(&mono));
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn frame_numbers_are_utf8() {
+        for value in [0, 0x7F, 0x80, 0x7FF, 0x800, 0xFFFF, 0x10000, 0x10FFFF] {
+            let mut bits = Bits::default();
+            write_utf8(&mut bits, value);
+            let mut utf8 = [0; 4];
+            let expected = char::from_u32(value).unwrap().encode_utf8(&mut utf8);
+            assert_eq!(bits.bytes, expected.as_bytes());
+        }
+    }
+}
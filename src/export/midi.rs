@@ -0,0 +1,130 @@
+use std::io::{self, Write};
+
+use crate::synth::Instrument;
+use crate::timeline::{Timeline, Voice};
+
+/// Ticks per quarter note.
+const DIVISION: u16 = 480;
+/// General MIDI keeps channel 10 for percussion.
+const DRUM_CHANNEL: u8 = 9;
+/// Controller of the pan, 64 being the center.
+const PAN: u8 = 10;
+/// Kinds of meta events carrying text.
+const TRACK_NAME: u8 = 0x03;
+const LYRIC: u8 = 0x05;
+const MARKER: u8 = 0x06;
+
+/// Writes a format 1 MIDI file with one track per voice.
+pub fn write(out: &mut impl Write, timeline: &Timeline) -> io::Result<()> {
+    let tracks = timeline.voices.len() as u16 + 1;
+    out.write_all(b"MThd")?;
+    out.write_all(&6u32.to_be_bytes())?;
+    out.write_all(&1u16.to_be_bytes())?;
+    out.write_all(&tracks.to_be_bytes())?;
+    out.write_all(&DIVISION.to_be_bytes())?;
+
+    // tempo track
+    let tempo = (60_000_000.0 / timeline.bpm) as u32;
+    let mut track = vec![0x00, 0xFF, 0x51, 0x03];
+    track.extend_from_slice(&tempo.to_be_bytes()[1..]);
+    write_track(out, track)?;
+
+    for (i, voice) in timeline.voices.iter().enumerate() {
+        write_track(out, voice_track(voice, &timeline.schedule(i), channel(i)))?;
+    }
+    out.flush()
+}
+
+/// Voices get a channel each, skipping the percussion channel.
+fn channel(voice: usize) -> u8 {
+    let channel = (voice % 15) as u8;
+    if channel >= DRUM_CHANNEL {
+        channel + 1
+    } else {
+        channel
+    }
+}
+
+fn ticks(duration: f32) -> u32 {
+    (duration * 4.0 * DIVISION as f32).round() as u32
+}
+
+/// `schedule` is when the voice's tones start and stop, see `Timeline::schedule`.
+fn voice_track(voice: &Voice, schedule: &[(f32, f32)], channel: u8) -> Vec<u8> {
+    // (tick, order, message). At the same tick note offs go first so that repeated
+    // notes don't cut themselves off, then markers, then lyrics before the notes
+    // they're sung on and pan changes before the notes they apply to.
+    let mut events: Vec<(u32, u8, Vec<u8>)> = Vec::new();
+    let mut schedule = schedule.iter();
+    // channels start centered
+    let mut pans = [0.0; 16];
+    for event in voice.events.iter() {
+        let channel = match event.instrument {
+            Instrument::Drums => DRUM_CHANNEL,
+            _ => channel,
+        };
+        for (i, tone) in event.tones.iter().enumerate() {
+            let Some(&(start, end)) = schedule.next() else {
+                break;
+            };
+            let key = tone.key.clamp(0, 127) as u8;
+            if let (0, Some(lyric)) = (i, &event.lyric) {
+                events.push((ticks(start), 2, text(LYRIC, lyric)));
+            }
+            if i == 0 && pans[channel as usize] != event.effects.pan {
+                pans[channel as usize] = event.effects.pan;
+                let value = (64.0 + event.effects.pan * 63.5).clamp(0.0, 127.0) as u8;
+                events.push((ticks(start), 2, vec![0xB0 | channel, PAN, value]));
+            }
+            events.push((ticks(start), 3, vec![0x90 | channel, key, tone.velocity]));
+            events.push((ticks(end), 0, vec![0x80 | channel, key, 0]));
+        }
+    }
+    for (time, name) in voice.markers.iter() {
+        events.push((ticks(*time), 1, text(MARKER, name)));
+    }
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+    let mut track = Vec::new();
+    write_text(&mut track, TRACK_NAME, &voice.name);
+    let mut last = 0;
+    for (tick, _, message) in events {
+        write_var_len(&mut track, tick - last);
+        last = tick;
+        track.extend_from_slice(&message);
+    }
+    track
+}
+
+/// A meta event carrying text, without its delta time.
+fn text(kind: u8, text: &str) -> Vec<u8> {
+    let mut event = vec![0xFF, kind];
+    write_var_len(&mut event, text.len() as u32);
+    event.extend_from_slice(text.as_bytes());
+    event
+}
+
+/// Writes a meta event carrying text at delta time 0.
+fn write_text(track: &mut Vec<u8>, kind: u8, text: &str) {
+    track.push(0x00);
+    track.extend_from_slice(&self::text(kind, text));
+}
+
+fn write_var_len(track: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    track.extend_from_slice(&bytes);
+}
+
+fn write_track(out: &mut impl Write, mut track: Vec<u8>) -> io::Result<()> {
+    // end of track
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    out.write_all(b"MTrk")?;
+    out.write_all(&(track.len() as u32).to_be_bytes())?;
+    out.write_all(&track)
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{synth, timeline::Timeline};
+
+pub mod flac;
+pub mod midi;
+pub mod raw;
+#[cfg(feature = "vorbis")]
+pub mod vorbis;
+pub mod wav;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Wav,
+    Flac,
+    Midi,
+    Raw(raw::Encoding),
+    #[cfg(feature = "vorbis")]
+    Vorbis,
+}
+
+impl Format {
+    /// `raw` is the same as `s16le`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            "mid" | "midi" => Some(Self::Midi),
+            "raw" | "s16le" => Some(Self::Raw(raw::Encoding::S16)),
+            "f32le" => Some(Self::Raw(raw::Encoding::F32)),
+            #[cfg(feature = "vorbis")]
+            "ogg" | "oga" | "vorbis" => Some(Self::Vorbis),
+            _ => None,
+        }
+    }
+
+    /// The format called `name`, or the one of `path`'s extension without a name.
+    pub fn new(name: Option<&str>, path: &Path) -> io::Result<Self> {
+        let name = name
+            .or_else(|| path.extension().and_then(|e| e.to_str()))
+            .unwrap_or("");
+        Self::from_name(name).ok_or_else(|| {
+            let message = match name.to_lowercase().as_str() {
+                "ogg" | "oga" | "vorbis" => {
+                    "Ogg Vorbis output needs the 'vorbis' feature".to_string()
+                }
+                _ => format!("Unknown output format '{name}'"),
+            };
+            io::Error::new(io::ErrorKind::InvalidInput, message)
+        })
+    }
+}
+
+/// Opens `path` for writing, `-` being stdout.
+pub fn create(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        Ok(Box::new(BufWriter::new(io::stdout())))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+/// Writes the timeline to `path` in `format`.
+pub fn save(timeline: &Timeline, path: &Path, format: Format) -> io::Result<()> {
+    let mut out = create(path)?;
+    match format {
+        Format::Wav => {
+            let samples = synth::render(timeline);
+            wav::write(&mut out, &samples, timeline.channels, timeline.sample_rate)
+        }
+        Format::Flac => {
+            let samples = synth::render(timeline);
+            flac::write(&mut out, &samples, timeline.channels, timeline.sample_rate)
+        }
+        #[cfg(feature = "vorbis")]
+        Format::Vorbis => {
+            let samples = synth::render(timeline);
+            vorbis::write(&mut out, &samples, timeline.channels, timeline.sample_rate)
+        }
+        Format::Midi => midi::write(&mut out, timeline),
+        Format::Raw(encoding) => {
+            raw::write(&mut out, &synth::render(timeline), encoding)?;
+            out.flush()
+        }
+    }
+}
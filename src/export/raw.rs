@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+
+use crate::audio::Sink;
+
+/// How the samples of raw audio are stored, little endian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    S16,
+    F32,
+}
+
+/// Writes samples as they are, without a header.
+pub fn write(out: &mut impl Write, samples: &[f32], encoding: Encoding) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(samples.len() * 4);
+    for sample in samples {
+        match encoding {
+            Encoding::S16 => {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+            Encoding::F32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+    out.write_all(&bytes)
+}
+
+/// A sink that writes raw audio to a file or a pipe as it's played.
+pub struct Writer {
+    out: Box<dyn Write>,
+    encoding: Encoding,
+}
+
+impl Writer {
+    pub fn new(out: Box<dyn Write>, encoding: Encoding) -> Self {
+        Self { out, encoding }
+    }
+}
+
+impl Sink for Writer {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        write(&mut self.out, samples, self.encoding)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Bytes written where the test can read them.
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_writes_samples_as_they_come() {
+        let bytes = Rc::new(RefCell::new(vec![]));
+        let mut writer = Writer::new(Box::new(Shared(bytes.clone())), Encoding::S16);
+        writer.write(&[0.0, 1.0]).unwrap();
+        assert_eq!(*bytes.borrow(), [0x00, 0x00, 0xFF, 0x7F]);
+        writer.write(&[-1.0, 0.5, 2.0]).unwrap();
+        let expected = [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x3F, 0xFF, 0x7F];
+        assert_eq!(*bytes.borrow(), expected);
+
+        let bytes = Rc::new(RefCell::new(vec![]));
+        let mut writer = Writer::new(Box::new(Shared(bytes.clone())), Encoding::F32);
+        writer.write(&[0.25, -2.0]).unwrap();
+        let expected: Vec<u8> = [0.25f32, -2.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        assert_eq!(*bytes.borrow(), expected);
+    }
+}
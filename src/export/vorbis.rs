@@ -0,0 +1,713 @@
+use std::f32::consts::PI;
+use std::io::{self, Write};
+
+/// Exponents of the two block sizes. Only long blocks are used, which smears
+/// sharp attacks a little but keeps the encoder simple.
+const SHORT: u32 = 8;
+const LONG: u32 = 11;
+const BLOCK: usize = 1 << LONG;
+/// Spectral lines of a block.
+const LINES: usize = BLOCK / 2;
+/// Floor values are in steps of `MULTIPLIER` of the decibel table, below `RANGE`.
+const MULTIPLIER: i32 = 2;
+const RANGE: i32 = 128;
+/// Points of the floor besides the first and the last, 4 per partition.
+const POSTS: usize = 24;
+/// Residues are coded in steps of 1/`STEPS` of the floor, up to `LARGEST` steps.
+const STEPS: i32 = 16;
+const LARGEST: i32 = 63;
+/// Spectral lines per residue partition.
+const PARTITION: usize = 32;
+/// Codebooks, in the order they're in the setup header.
+const FLOOR_BOOK: usize = 0;
+const CLASS_BOOK: usize = 1;
+const RESIDUE_BOOK: usize = 2;
+
+/// Writes samples as Ogg Vorbis, interleaved if there's more than one channel.
+pub fn write(
+    out: &mut impl Write,
+    samples: &[f32],
+    channels: u16,
+    sample_rate: u32,
+) -> io::Result<()> {
+    let channels = channels as usize;
+    let frames = samples.len() / channels;
+    let encoder = Encoder::new();
+    let mut ogg = Ogg::new(out);
+
+    let mut identification = Packet::header(1);
+    identification.write(0, 32);
+    identification.write(channels as u32, 8);
+    identification.write(sample_rate, 32);
+    // maximum, nominal and minimum bitrates, unknown
+    identification.write(0, 32);
+    identification.write(0, 32);
+    identification.write(0, 32);
+    identification.write(SHORT, 4);
+    identification.write(LONG, 4);
+    identification.write(1, 1);
+    ogg.packet(&identification.bytes, 0)?;
+    ogg.flush(false)?;
+
+    let mut comments = Packet::header(3);
+    let vendor = b"musical";
+    comments.write(vendor.len() as u32, 32);
+    vendor.iter().for_each(|b| comments.write(*b as u32, 8));
+    comments.write(0, 32);
+    comments.write(1, 1);
+    ogg.packet(&comments.bytes, 0)?;
+    ogg.packet(&encoder.setup().bytes, 0)?;
+    ogg.flush(false)?;
+
+    // block k covers the frames from (k - 1) * LINES to (k + 1) * LINES, and its
+    // packet gives back the frames the first half of it overlaps the block before it
+    let blocks = frames.div_ceil(LINES) + 1;
+    let mut input = vec![0.0; BLOCK];
+    for k in 0..blocks {
+        let spectra: Vec<Vec<f32>> = (0..channels)
+            .map(|c| {
+                for (i, sample) in input.iter_mut().enumerate() {
+                    *sample = (k * LINES + i)
+                        .checked_sub(LINES)
+                        .filter(|frame| *frame < frames)
+                        .map_or(0.0, |frame| samples[frame * channels + c]);
+                }
+                encoder.mdct(&input)
+            })
+            .collect();
+        let granule = (k * LINES).min(frames) as u64;
+        ogg.packet(&encoder.audio(&spectra).bytes, granule)?;
+    }
+    ogg.flush(true)?;
+    out.flush()
+}
+
+struct Encoder {
+    books: [Codebook; 3],
+    /// X of each point of the floor, the first two being its ends.
+    posts: Vec<i32>,
+    /// The points before each point in `posts` that are the closest to it, on
+    /// its left and right.
+    neighbours: Vec<(usize, usize)>,
+    window: Vec<f32>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        // floor values and residues are mostly small
+        let floor = (0..RANGE)
+            .map(|v| 1.0 / (v as f64 + 1.0).powi(2))
+            .collect::<Vec<_>>();
+        let residue = (-LARGEST..=LARGEST)
+            .map(|v| 1.0 / (v.abs() as f64 + 1.0).powi(2))
+            .collect::<Vec<_>>();
+        let books = [
+            Codebook::new(&floor),
+            Codebook::new(&[1.0, 1.0]),
+            Codebook::new(&residue),
+        ];
+
+        // closer together in the low end, where most of what's heard is
+        let mut sorted: Vec<i32> = vec![];
+        let mut x = 1.0f32;
+        while sorted.len() < POSTS {
+            let post = (x.round() as i32).max(sorted.last().map_or(1, |l| l + 1));
+            sorted.push(post);
+            x *= 1.3;
+        }
+        // halving the ranges between them so that each is predicted from points
+        // on both of its sides
+        let mut posts = vec![0, LINES as i32];
+        let mut ranges = vec![(0, POSTS)];
+        while let Some((start, end)) = ranges.pop() {
+            if start < end {
+                let middle = (start + end) / 2;
+                posts.push(sorted[middle]);
+                ranges.push((middle + 1, end));
+                ranges.push((start, middle));
+            }
+        }
+        let neighbours = (0..posts.len())
+            .map(|i| {
+                let before = &posts[..i];
+                let low = (0..i)
+                    .filter(|j| before[*j] < posts[i])
+                    .max_by_key(|j| before[*j]);
+                let high = (0..i)
+                    .filter(|j| before[*j] > posts[i])
+                    .min_by_key(|j| before[*j]);
+                (low.unwrap_or(0), high.unwrap_or(0))
+            })
+            .collect();
+
+        let window = (0..BLOCK)
+            .map(|i| {
+                let x = ((i as f32 + 0.5) / BLOCK as f32 * PI).sin();
+                (PI / 2.0 * x * x).sin()
+            })
+            .collect();
+        Self {
+            books,
+            posts,
+            neighbours,
+            window,
+        }
+    }
+
+    fn setup(&self) -> Packet {
+        let mut setup = Packet::header(5);
+        setup.write(self.books.len() as u32 - 1, 8);
+        self.books[FLOOR_BOOK].write_header(&mut setup, None);
+        self.books[CLASS_BOOK].write_header(&mut setup, None);
+        let minimum = -LARGEST as f32 / STEPS as f32;
+        self.books[RESIDUE_BOOK].write_header(&mut setup, Some((minimum, 1.0 / STEPS as f32)));
+
+        // one unused time domain transform
+        setup.write(0, 6);
+        setup.write(0, 16);
+
+        // one floor of type 1, with one class of 4 points
+        setup.write(0, 6);
+        setup.write(1, 16);
+        let partitions = POSTS / 4;
+        setup.write(partitions as u32, 5);
+        for _ in 0..partitions {
+            setup.write(0, 4);
+        }
+        setup.write(3, 3);
+        setup.write(0, 2);
+        setup.write(FLOOR_BOOK as u32 + 1, 8);
+        setup.write(MULTIPLIER as u32 - 1, 2);
+        setup.write(LONG - 1, 4);
+        for x in self.posts[2..].iter() {
+            setup.write(*x as u32, LONG - 1);
+        }
+
+        // one residue of type 1, whose partitions are either silent or coded
+        setup.write(0, 6);
+        setup.write(1, 16);
+        setup.write(0, 24);
+        setup.write(LINES as u32, 24);
+        setup.write(PARTITION as u32 - 1, 24);
+        setup.write(1, 6);
+        setup.write(CLASS_BOOK as u32, 8);
+        setup.write(0, 4);
+        setup.write(1, 4);
+        setup.write(RESIDUE_BOOK as u32, 8);
+
+        // one mapping, which uses them for every channel
+        setup.write(0, 6);
+        setup.write(0, 16);
+        setup.write(0, 4);
+        setup.write(0, 8);
+        setup.write(0, 8);
+        setup.write(0, 8);
+
+        // one mode, of long blocks
+        setup.write(0, 6);
+        setup.write(1, 1);
+        setup.write(0, 16);
+        setup.write(0, 16);
+        setup.write(0, 8);
+        setup.write(1, 1);
+        setup
+    }
+
+    /// The packet of a block, given the spectrum of each channel.
+    fn audio(&self, spectra: &[Vec<f32>]) -> Packet {
+        let mut packet = Packet::default();
+        packet.write(0, 1);
+        // the blocks before and after are long too
+        packet.write(1, 1);
+        packet.write(1, 1);
+        let residues: Vec<Option<Vec<i32>>> = spectra
+            .iter()
+            .map(|spectrum| {
+                let floor = self.floor(&mut packet, spectrum)?;
+                let residue = spectrum
+                    .iter()
+                    .zip(floor)
+                    .map(|(x, floor)| {
+                        let steps = (x / floor * STEPS as f32).round() as i32;
+                        steps.clamp(-LARGEST, LARGEST)
+                    })
+                    .collect();
+                Some(residue)
+            })
+            .collect();
+        // channels without a floor have no residue either
+        let residues: Vec<&Vec<i32>> = residues.iter().flatten().collect();
+        for partition in 0..LINES / PARTITION {
+            let lines = partition * PARTITION..(partition + 1) * PARTITION;
+            let coded: Vec<bool> = residues
+                .iter()
+                .map(|r| r[lines.clone()].iter().any(|v| *v != 0))
+                .collect();
+            for coded in coded.iter() {
+                self.books[CLASS_BOOK].write(&mut packet, *coded as usize);
+            }
+            for (residue, coded) in residues.iter().zip(coded) {
+                if coded {
+                    for value in residue[lines.clone()].iter() {
+                        self.books[RESIDUE_BOOK].write(&mut packet, (value + LARGEST) as usize);
+                    }
+                }
+            }
+        }
+        packet
+    }
+
+    /// Writes a floor that's just above the loudest lines around each of its points,
+    /// and gives back its value at every line. `None` if the spectrum is silent.
+    fn floor(&self, packet: &mut Packet, spectrum: &[f32]) -> Option<Vec<f32>> {
+        let loudest = spectrum.iter().fold(0.0f32, |a, x| a.max(x.abs()));
+        if loudest < decibels(0) {
+            packet.write(0, 1);
+            return None;
+        }
+        packet.write(1, 1);
+        let mut sorted = self.posts.clone();
+        sorted.sort();
+        // everything between a point and the points next to it is below it, so
+        // that lines between the points are above what's between them
+        let y: Vec<i32> = self
+            .posts
+            .iter()
+            .map(|x| {
+                let i = sorted.binary_search(x).unwrap();
+                let start = sorted[i.saturating_sub(1)] as usize;
+                let end = sorted[(i + 1).min(sorted.len() - 1)] as usize;
+                let level = spectrum[start..end.min(LINES)]
+                    .iter()
+                    .fold(0.0f32, |a, x| a.max(x.abs()));
+                let index = (level / decibels(0)).ln() / (1.0 / decibels(0)).ln() * 255.0;
+                ((index / MULTIPLIER as f32).ceil() as i32).clamp(0, RANGE - 1)
+            })
+            .collect();
+
+        let bits = 32 - (RANGE as u32 - 1).leading_zeros();
+        packet.write(y[0] as u32, bits);
+        packet.write(y[1] as u32, bits);
+        let mut used = vec![false; y.len()];
+        used[0] = true;
+        used[1] = true;
+        for i in 2..y.len() {
+            let (low, high) = self.neighbours[i];
+            let x = &self.posts;
+            let predicted = render_point(x[low], y[low], x[high], y[high], x[i]);
+            let value = floor_value(y[i], predicted);
+            if value != 0 {
+                used[low] = true;
+                used[high] = true;
+                used[i] = true;
+            }
+            self.books[FLOOR_BOOK].write(packet, value as usize);
+        }
+
+        // what the decoder draws from it, points that are on the lines between
+        // their neighbours being left out
+        let mut order: Vec<usize> = (0..y.len()).collect();
+        order.sort_by_key(|i| self.posts[*i]);
+        let mut curve = vec![0; LINES];
+        let (mut lx, mut ly) = (0, y[0] * MULTIPLIER);
+        for i in order.into_iter().skip(1).filter(|i| used[*i]) {
+            let (hx, hy) = (self.posts[i], y[i] * MULTIPLIER);
+            render_line(lx, ly, hx, hy, &mut curve);
+            (lx, ly) = (hx, hy);
+        }
+        Some(curve.into_iter().map(|v| decibels(v as usize)).collect())
+    }
+
+    /// The lines of a block, windowed and transformed by an MDCT.
+    fn mdct(&self, input: &[f32]) -> Vec<f32> {
+        let n = BLOCK;
+        let quarter = n / 4;
+        let x: Vec<f32> = input.iter().zip(&self.window).map(|(x, w)| x * w).collect();
+        // the MDCT of the quarters a, b, c and d is the DCT-IV of -c'-d, a-b', where '
+        // is reversed
+        let mut folded = vec![0.0; LINES];
+        for i in 0..quarter {
+            folded[i] = -x[3 * quarter - 1 - i] - x[3 * quarter + i];
+            folded[quarter + i] = x[i] - x[2 * quarter - 1 - i];
+        }
+        // the DCT-IV as an FFT of half its size
+        let m = LINES as f32;
+        let mut z: Vec<(f32, f32)> = (0..LINES / 2)
+            .map(|i| {
+                let angle = -PI * (i as f32 + 0.25) / m;
+                multiply((folded[2 * i], folded[LINES - 1 - 2 * i]), angle)
+            })
+            .collect();
+        fft(&mut z);
+        // scaled so that the decoder's inverse gives the samples back
+        let scale = 4.0 / n as f32;
+        let mut lines = vec![0.0; LINES];
+        for (k, z) in z.into_iter().enumerate() {
+            let (re, im) = multiply(z, -PI * k as f32 / m);
+            lines[2 * k] = re * scale;
+            lines[LINES - 1 - 2 * k] = -im * scale;
+        }
+        lines
+    }
+}
+
+/// What codes the floor value `y` at a point whose value is predicted to be
+/// `predicted` from its neighbours.
+fn floor_value(y: i32, predicted: i32) -> i32 {
+    let (high, low) = (RANGE - predicted, predicted);
+    let room = high.min(low) * 2;
+    let delta = y - predicted;
+    if delta >= 0 && delta * 2 < room {
+        delta * 2
+    } else if delta < 0 && -delta * 2 - 1 < room {
+        -delta * 2 - 1
+    } else if high > low {
+        delta + low
+    } else {
+        high - 1 - delta
+    }
+}
+
+/// The value at `x` of the line from (x0, y0) to (x1, y1), rounded like decoders do.
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let offset = dy.abs() * (x - x0) / (x1 - x0);
+    if dy < 0 {
+        y0 - offset
+    } else {
+        y0 + offset
+    }
+}
+
+/// Draws the line from (x0, y0) to just before x1 into `values`, like decoders do.
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, values: &mut [i32]) {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let (mut y, mut error) = (y0, 0);
+    for x in x0..x1.min(values.len() as i32) {
+        if x > x0 {
+            error += ady;
+            if error >= adx {
+                error -= adx;
+                y += step;
+            } else {
+                y += base;
+            }
+        }
+        values[x as usize] = y;
+    }
+}
+
+/// The floor amplitude at `index` in the decibel table of decoders, which goes
+/// from -140 dB to 0 dB.
+fn decibels(index: usize) -> f32 {
+    let lowest = 1.0649863e-07f32;
+    lowest * (1.0 / lowest).powf(index as f32 / 255.0)
+}
+
+/// Turns `z` by `angle`.
+fn multiply((re, im): (f32, f32), angle: f32) -> (f32, f32) {
+    let (sin, cos) = angle.sin_cos();
+    (re * cos - im * sin, re * sin + im * cos)
+}
+
+/// An in place radix-2 FFT.
+fn fft(data: &mut [(f32, f32)]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (a, b) = (data[start + k], data[start + k + length / 2]);
+                let b = multiply(b, -2.0 * PI * k as f32 / length as f32);
+                data[start + k] = (a.0 + b.0, a.1 + b.1);
+                data[start + k + length / 2] = (a.0 - b.0, a.1 - b.1);
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// Huffman codes of the entries of a codebook.
+struct Codebook {
+    lengths: Vec<u32>,
+    codes: Vec<u32>,
+}
+
+impl Codebook {
+    /// Codes for entries that are used about as often as their `weights`.
+    fn new(weights: &[f64]) -> Self {
+        // (weight, entries under the node)
+        let mut nodes: Vec<(f64, Vec<usize>)> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (*w, vec![i]))
+            .collect();
+        let mut lengths = vec![0; weights.len()];
+        while nodes.len() > 1 {
+            nodes.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let (a, mut entries) = nodes.pop().unwrap();
+            let (b, others) = nodes.pop().unwrap();
+            entries.extend(others);
+            entries.iter().for_each(|e| lengths[*e] += 1);
+            nodes.push((a + b, entries));
+        }
+        // decoders give each entry the lowest code of its length that's left, in order
+        let mut next = [0u32; 33];
+        let mut codes = vec![];
+        for length in lengths.iter().map(|l| *l as usize) {
+            let code = next[length];
+            codes.push(code);
+            for j in (1..=length).rev() {
+                if next[j] & 1 == 1 {
+                    next[j] = if j == 1 {
+                        next[1] + 1
+                    } else {
+                        next[j - 1] << 1
+                    };
+                    break;
+                }
+                next[j] += 1;
+            }
+            let mut entry = code;
+            for j in length + 1..33 {
+                if next[j] >> 1 != entry {
+                    break;
+                }
+                entry = next[j];
+                next[j] = next[j - 1] << 1;
+            }
+        }
+        Self { lengths, codes }
+    }
+
+    /// Writes the codebook for the setup header. Codebooks with a `lookup` hold
+    /// numbers from its minimum on, in steps of its delta.
+    fn write_header(&self, packet: &mut Packet, lookup: Option<(f32, f32)>) {
+        packet.write(0x564342, 24);
+        packet.write(1, 16);
+        packet.write(self.lengths.len() as u32, 24);
+        packet.write(0, 2);
+        for length in self.lengths.iter() {
+            packet.write(length - 1, 5);
+        }
+        let Some((minimum, delta)) = lookup else {
+            packet.write(0, 4);
+            return;
+        };
+        packet.write(1, 4);
+        packet.write(float(minimum), 32);
+        packet.write(float(delta), 32);
+        let bits = 32 - (self.lengths.len() as u32 - 1).leading_zeros();
+        packet.write(bits - 1, 4);
+        packet.write(0, 1);
+        for i in 0..self.lengths.len() {
+            packet.write(i as u32, bits);
+        }
+    }
+
+    fn write(&self, packet: &mut Packet, entry: usize) {
+        let (length, code) = (self.lengths[entry], self.codes[entry]);
+        // decoders read codes from their first bit on
+        for i in (0..length).rev() {
+            packet.write(code >> i & 1, 1);
+        }
+    }
+}
+
+/// `value` in the format of floats in codebooks.
+fn float(value: f32) -> u32 {
+    if value == 0.0 {
+        return 0;
+    }
+    let exponent = value.abs().log2().floor() as i32;
+    let mantissa = (value.abs() * 2f32.powi(20 - exponent)) as u32;
+    let sign = if value < 0.0 { 1 << 31 } else { 0 };
+    sign | ((exponent - 20 + 788) as u32) << 21 | mantissa
+}
+
+/// Bits packed from the least significant one on.
+#[derive(Default)]
+struct Packet {
+    bytes: Vec<u8>,
+    /// Bits used of the last byte, 0 if it's full.
+    used: u32,
+}
+
+impl Packet {
+    /// A header packet of type `kind`.
+    fn header(kind: u32) -> Self {
+        let mut packet = Self::default();
+        packet.write(kind, 8);
+        b"vorbis".iter().for_each(|b| packet.write(*b as u32, 8));
+        packet
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        for i in 0..count {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+/// Packets put into Ogg pages.
+struct Ogg<'a, W: Write> {
+    out: &'a mut W,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    /// Frames decoded once the last packet of the page is.
+    granule: u64,
+}
+
+impl<'a, W: Write> Ogg<'a, W> {
+    /// Identifies the stream in the file.
+    const SERIAL: u32 = 0x6d757369;
+
+    fn new(out: &'a mut W) -> Self {
+        Self {
+            out,
+            sequence: 0,
+            segments: vec![],
+            data: vec![],
+            granule: 0,
+        }
+    }
+
+    fn packet(&mut self, packet: &[u8], granule: u64) -> io::Result<()> {
+        let segments = packet.len() / 255 + 1;
+        if self.segments.len() + segments > 255 || self.data.len() > 4096 {
+            self.flush(false)?;
+        }
+        self.segments.extend(vec![255; packet.len() / 255]);
+        self.segments.push((packet.len() % 255) as u8);
+        self.data.extend_from_slice(packet);
+        self.granule = granule;
+        Ok(())
+    }
+
+    /// Writes the packets so far as a page, `last` being the end of the stream.
+    fn flush(&mut self, last: bool) -> io::Result<()> {
+        let mut page = b"OggS\0".to_vec();
+        let first = if self.sequence == 0 { 0x02 } else { 0 };
+        page.push(first | if last { 0x04 } else { 0 });
+        page.extend_from_slice(&self.granule.to_le_bytes());
+        page.extend_from_slice(&Self::SERIAL.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.segments.len() as u8);
+        page.append(&mut self.segments);
+        page.append(&mut self.data);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.sequence += 1;
+        self.out.write_all(&page)
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for byte in bytes {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lewton::inside_ogg::OggStreamReader;
+    use std::io::Cursor;
+
+    #[test]
+    fn codebooks_are_complete_prefix_codes() {
+        for book in Encoder::new().books.iter() {
+            let entries: Vec<(u32, u32)> = book
+                .lengths
+                .iter()
+                .copied()
+                .zip(book.codes.iter().copied())
+                .collect();
+            let kraft: f64 = entries.iter().map(|(l, _)| 0.5f64.powi(*l as i32)).sum();
+            assert_eq!(kraft, 1.0);
+            for (i, (length, code)) in entries.iter().enumerate() {
+                for (longer, other) in entries[i + 1..].iter().filter(|(l, _)| l >= length) {
+                    assert_ne!(other >> (longer - length), *code);
+                }
+                for (shorter, other) in entries[i + 1..].iter().filter(|(l, _)| l < length) {
+                    assert_ne!(code >> (length - shorter), *other);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(crc32(b"123456789"), 0x89A1897F);
+    }
+
+    #[test]
+    fn headers() {
+        let mut bytes = vec![];
+        write(&mut bytes, &[0.0; 200], 2, 48000).unwrap();
+        let reader = OggStreamReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.ident_hdr.audio_channels, 2);
+        assert_eq!(reader.ident_hdr.audio_sample_rate, 48000);
+        assert_eq!(reader.ident_hdr.blocksize_0, SHORT as u8);
+        assert_eq!(reader.ident_hdr.blocksize_1, LONG as u8);
+        assert_eq!(reader.comment_hdr.vendor, "musical");
+    }
+
+    #[test]
+    fn decodes_to_what_was_written() {
+        let frames = 44100;
+        let tone = |freq: f32, i: usize| (i as f32 / 44100.0 * freq * 2.0 * PI).sin() * 0.4;
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| [tone(440.0, i) + tone(1320.0, i) * 0.3, tone(330.0, i)])
+            .collect();
+        let mut bytes = vec![];
+        write(&mut bytes, &samples, 2, 44100).unwrap();
+        let mut reader = OggStreamReader::new(Cursor::new(bytes)).unwrap();
+        let mut decoded: Vec<i16> = vec![];
+        while let Some(packet) = reader.read_dec_packet_itl().unwrap() {
+            decoded.extend(packet);
+        }
+        assert_eq!(decoded.len(), samples.len());
+        let (mut signal, mut noise) = (0.0, 0.0);
+        for (x, y) in samples.iter().zip(&decoded) {
+            let y = *y as f32 / i16::MAX as f32;
+            signal += x * x;
+            noise += (x - y) * (x - y);
+        }
+        let snr = 10.0 * (signal / noise).log10();
+        assert!(snr > 20.0, "{snr} dB");
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::parser::{
+    data::Value,
+    error::ParseResponse,
+    parser::{MusicalValues, Parser, ParsingFunctions},
+};
+use crate::CompilerError;
+
+/// Commands whose second half is the name of a label.
+const LABEL_COMMANDS: &[&str] = &["goto", "spawn", "transpose"];
+
+/// Parses a file together with everything it includes or imports.
+pub struct Loader {
+    /// files that are currently being loaded, used to detect include cycles.
+    stack: Vec<PathBuf>,
+    loaded: HashSet<(PathBuf, String)>,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        let stack = Vec::new();
+        let loaded = HashSet::new();
+        Self { stack, loaded }
+    }
+
+    /// `input` is the content of the file at `path`.
+    pub fn load(&mut self, input: &str, path: &Path) -> Result<Vec<MusicalValues>, CompilerError> {
+        self.load_module(input, path, "")
+    }
+
+    fn load_file(
+        &mut self,
+        path: &Path,
+        prefix: &str,
+    ) -> Result<Vec<MusicalValues>, CompilerError> {
+        let name = path.display().to_string();
+        let canonical =
+            fs::canonicalize(path).map_err(|_| CompilerError::ReadFailed(name.clone()))?;
+        if self.stack.contains(&canonical) {
+            return Err(CompilerError::IncludeCycle(name));
+        }
+        if !self.loaded.insert((canonical, prefix.into())) {
+            // already loaded by another file
+            return Ok(vec![]);
+        }
+        let input = fs::read_to_string(path).map_err(|_| CompilerError::ReadFailed(name))?;
+        self.load_module(&input, path, prefix)
+    }
+
+    /// Labels defined by the module get `prefix` prepended to their name.
+    fn load_module(
+        &mut self,
+        input: &str,
+        path: &Path,
+        prefix: &str,
+    ) -> Result<Vec<MusicalValues>, CompilerError> {
+        let canonical = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        self.stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut parser = Parser::from(input);
+        let mut ast = Vec::new();
+        let mut has_failed = false;
+        while parser.has_next() {
+            if has_failed {
+                break;
+            }
+            let next = parser.next().map(|mut value| {
+                resolve_sources(&mut value, &dir);
+                value
+            });
+            match next {
+                Ok(MusicalValues::Include(file)) => {
+                    ast.extend(self.load_file(&dir.join(file), prefix)?);
+                }
+                Ok(MusicalValues::Import(module)) => {
+                    let file = dir.join(module.replace('.', "/")).with_extension("musical");
+                    let namespace = if prefix.is_empty() {
+                        module.clone()
+                    } else {
+                        format!("{prefix}.{module}")
+                    };
+                    let mut values = self.load_file(&file, &namespace)?;
+                    rename_label_commands(&mut values, &namespace);
+                    for value in values {
+                        match value {
+                            MusicalValues::Label(_) => ast.push(value),
+                            e => eprintln!(
+                                "Warning: ignoring instruction {e} imported from {module}"
+                            ),
+                        }
+                    }
+                }
+                Ok(MusicalValues::Label(mut label)) => {
+                    if !prefix.is_empty() {
+                        label.name = format!("{prefix}.{}", label.name);
+                    }
+                    ast.push(MusicalValues::Label(label));
+                }
+                Ok(v) => ast.push(v),
+                Err(e) => {
+                    use ParseResponse as E;
+                    match e {
+                        E::Done => break,
+                        E::NotPossible => unreachable!(),
+                        e => {
+                            eprintln!("{}: {e}{}", path.display(), parser.get_err_line());
+                            has_failed = true;
+                        }
+                    }
+                }
+            }
+        }
+        self.stack.pop();
+        if has_failed {
+            return Err(CompilerError::Failed);
+        }
+        Ok(ast)
+    }
+}
+
+/// Makes the paths of instruments read from files relative to the file that uses
+/// them, which is in `dir`.
+fn resolve_sources(value: &mut MusicalValues, dir: &Path) {
+    match value {
+        MusicalValues::Var(v) => {
+            if let Value::Source(source) = &mut v.value {
+                let path = source.path_mut();
+                *path = dir.join(&path).display().to_string();
+            }
+        }
+        MusicalValues::Label(label) => {
+            for value in label.body.iter_mut() {
+                resolve_sources(value, dir);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Makes commands inside of an imported module refer to the module's own labels,
+/// e.g. `goto intro` becomes `goto motifs.intro`.
+fn rename_label_commands(ast: &mut [MusicalValues], namespace: &str) {
+    let labels: HashSet<String> = ast
+        .iter()
+        .filter_map(|v| match v {
+            MusicalValues::Label(l) => Some(l.name.clone()),
+            _ => None,
+        })
+        .collect();
+    for value in ast.iter_mut() {
+        let MusicalValues::Label(label) = value else {
+            continue;
+        };
+        for value in label.body.iter_mut() {
+            if let MusicalValues::Pair((command, target), _) = value {
+                let name = format!("{namespace}.{target}");
+                if LABEL_COMMANDS.contains(&command.to_lowercase().as_str())
+                    && labels.contains(&name)
+                {
+                    *target = name;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for the test called `name`, with `files` in it.
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("musical-{}-{name}", std::process::id()));
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &Path, file: &str) -> Result<Vec<MusicalValues>, CompilerError> {
+        let path = dir.join(file);
+        Loader::new().load(&fs::read_to_string(&path).unwrap(), &path)
+    }
+
+    fn labels(ast: &[MusicalValues]) -> Vec<&str> {
+        ast.iter()
+            .filter_map(|v| match v {
+                MusicalValues::Label(l) => Some(l.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The targets of the commands in the label called `name`.
+    fn targets<'a>(ast: &'a [MusicalValues], name: &str) -> Vec<&'a str> {
+        let label = ast.iter().find_map(|v| match v {
+            MusicalValues::Label(l) if l.name == name => Some(l),
+            _ => None,
+        });
+        (label.unwrap().body.iter())
+            .filter_map(|v| match v {
+                MusicalValues::Pair((_, target), _) => Some(target.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = dir(
+            "cycle",
+            &[
+                ("cyc1.musical", "include \"cyc2.musical\"\n"),
+                ("cyc2.musical", "include \"cyc1.musical\"\n"),
+            ],
+        );
+        let result = load(&dir, "cyc1.musical");
+        assert!(
+            matches!(result, Err(CompilerError::IncludeCycle(f)) if f.ends_with("cyc1.musical"))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_loaded_once() {
+        let dir = dir(
+            "once",
+            &[
+                (
+                    "main.musical",
+                    "include \"a.musical\"\ninclude \"b.musical\"\n",
+                ),
+                ("a.musical", "include \"common.musical\"\n@a {\nC 1\n}\n"),
+                ("b.musical", "include \"common.musical\"\n@b {\nC 1\n}\n"),
+                ("common.musical", "@common {\nC 1\n}\n"),
+            ],
+        );
+        let ast = load(&dir, "main.musical").unwrap();
+        assert_eq!(labels(&ast), ["common", "a", "b"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn imports_are_read_from_directories_and_prefixed() {
+        let dir = dir(
+            "import",
+            &[
+                ("main.musical", "import a.b\n@main {\ngoto a.b.tune\n}\n"),
+                ("a/b.musical", "@intro {\nC 1\n}\n@tune {\ngoto intro\n}\n"),
+            ],
+        );
+        let ast = load(&dir, "main.musical").unwrap();
+        assert_eq!(labels(&ast), ["a.b.intro", "a.b.tune", "main"]);
+        assert_eq!(targets(&ast, "a.b.tune"), ["a.b.intro"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_commands_on_the_module_labels_are_renamed() {
+        let module =
+            "@intro {\nC 1\n}\n@tune {\ngoto intro\nspawn main\ntranspose intro 2\ninc intro\n}\n";
+        // the labels of the module are prefixed as it's loaded
+        let path = Path::new("motifs.musical");
+        let mut ast = Loader::new().load_module(module, path, "motifs").unwrap();
+        rename_label_commands(&mut ast, "motifs");
+        let renamed = ["motifs.intro", "main", "motifs.intro", "intro"];
+        assert_eq!(targets(&ast, "motifs.tune"), renamed);
+    }
+}
@@ -1,33 +1,89 @@
 mod parser;
-use audio::AudioDevice;
+use audio::{AudioDevice, Stream, SystemClock};
+use cli::CompilerArgs;
+use effects::Effects;
+use export::Format;
+use loader::Loader;
 use parser::{
-    data::Chord,
-    error::ParseResponse,
-    parser::{MusicalValues, Parser, ParsingFunctions},
+    data::{
+        dynamic_velocity, mode_steps, note_semitone, Chord, Degree, Hit, Note, Pitch, Source, Value,
+    },
+    parser::MusicalValues,
 };
+use random::Random;
+use sample::Sample;
+use soundfont::SoundFont;
 use std::error::Error;
-use std::{collections::HashMap, fmt::Display, fs, process::exit};
+use std::{collections::HashMap, env, fmt::Display, fs, mem, path::Path, process::exit, sync::Arc};
+use synth::{Envelope, Instrument, Waveform, SAMPLE_RATE};
+use timeline::{Arpeggio, Timeline, Timing, Tone, Voice};
 
 mod audio;
-
-fn play(chord: &Chord, _base: f32, _octave: f32) {
-    for _note in chord.notes.iter() {
-        // println!("playing note {note:?}, base = {base}, octave = {octave}");
-    }
-}
+mod cli;
+mod effects;
+mod export;
+mod loader;
+mod random;
+mod sample;
+mod soundfont;
+mod synth;
+mod timeline;
 
 pub struct Compiler {
     pub ast: Vec<MusicalValues>,
+    pub timeline: Timeline,
     variables: VariableType,
+    /// index of the label in `ast` that's being run.
+    label: usize,
     cursor: usize,
+    /// labels that called the one being run, the innermost last.
+    callers: Vec<Caller>,
+    /// index of the voice in `timeline` that chords are played on.
+    voice: usize,
+    /// semitones added by the `transpose` command, on top of the `transpose` variable.
+    transposition: i32,
+    random: Random,
+    /// voices that are waiting for their turn, the one above being played.
+    parts: Vec<Part>,
+    /// samples read so far, by their path.
+    samples: HashMap<String, Arc<Sample>>,
+    soundfonts: HashMap<String, Arc<SoundFont>>,
     function_pointer: HashMap<String, usize>,
     audio_device: Option<AudioDevice>,
+    /// Overrides the `samplerate` setting.
+    pub sample_rate: Option<u32>,
+    /// Where the audio is played while the program runs, if it's streamed.
+    pub stream: Option<Stream>,
+    /// Whether what's been streamed is dropped from the timeline, which it can't be
+    /// if the timeline is saved once the program is over.
+    pub forget: bool,
+}
+
+/// Where a voice is at in the program. Voices are played in turn, the one that's
+/// the furthest behind first, so that each of them can loop forever.
+struct Part {
+    label: usize,
+    cursor: usize,
+    callers: Vec<Caller>,
+    voice: usize,
+    transposition: i32,
+    scoped: HashMap<String, Value>,
+    random: Random,
+}
+
+/// A label that went to another one, carried on with once that returns.
+struct Caller {
+    label: usize,
+    /// What comes after the `goto`.
+    cursor: usize,
+    transposition: i32,
+    scoped: HashMap<String, Value>,
 }
 
 #[derive(Debug)]
 pub struct VariableType {
-    pub global: HashMap<String, f32>,   // modified once
-    pub scoped: HashMap<String, f32>, // cleared once entering a scope.
+    pub global: HashMap<String, Value>,   // modified once
+    pub scoped: HashMap<String, Value>, // cleared once entering a scope.
 }
 
 impl VariableType {
@@ -38,16 +94,40 @@ impl VariableType {
     }
 
     pub fn get_global(&self, var: &str) -> Result<f32, CompilerError> {
-        if let Some(var) = self.global.get(var) {
-            Ok(*var)
-        } else {
-            Err(CompilerError::GlobalPropertyMissing(var.into()))
+        match self.global.get(var) {
+            Some(Value::Num(n)) => Ok(*n),
+            Some(_) => Err(CompilerError::NotANumber(var.into())),
+            None => Err(CompilerError::GlobalPropertyMissing(var.into())),
         }
     }
+
+    /// Looks `var` up in the current scope first, then globally.
+    pub fn get(&self, var: &str) -> Result<f32, CompilerError> {
+        match self.scoped.get(var) {
+            Some(Value::Num(n)) => Ok(*n),
+            Some(_) => Err(CompilerError::NotANumber(var.into())),
+            None => self.get_global(var),
+        }
+    }
+
+    /// Like `get`, but `default` if the variable doesn't exist.
+    pub fn get_or(&self, var: &str, default: f32) -> Result<f32, CompilerError> {
+        match self.get_value(var) {
+            Some(Value::Num(n)) => Ok(*n),
+            Some(_) => Err(CompilerError::NotANumber(var.into())),
+            None => Ok(default),
+        }
+    }
+
+    /// Like `get`, but for variables that don't need to be numbers.
+    pub fn get_value(&self, var: &str) -> Option<&Value> {
+        self.scoped.get(var).or(self.global.get(var))
+    }
 }
 
 impl<'a> Compiler {
-    pub fn new(input: &'a str) -> Result<Self, CompilerError> {
+    /// `path` is where `input` was read from, includes and imports are relative to it.
+    pub fn new(input: &'a str, path: &Path) -> Result<Self, CompilerError> {
         let audio_device = if AudioDevice::supports() {
             if cfg!(debug_assertions) {
                 AudioDevice::enable_debug_mode();
@@ -63,131 +143,164 @@ impl<'a> Compiler {
             None
         };
 
-        let mut parser = Parser::from(input);
-        let mut ast = Vec::new();
-        let mut has_failed = false;
+        let ast = Loader::new().load(input, path)?;
         let mut function_pointer = HashMap::new();
-        let mut p = 0;
-        while parser.has_next() {
-            let mut label_name = "".to_string();
-            if has_failed {
-                break;
-            }
-            match parser.next() {
-                Ok(v) => {
-                    match &v {
-                        MusicalValues::Label(name) => {
-                            label_name += &name;
-                            function_pointer.insert(label_name, p);
-                        }
-                        _ => (),
-                    };
-                    ast.push(v);
-                }
-                Err(e) => {
-                    use ParseResponse as E;
-                    match e {
-                        E::Done => break,
-                        E::NotPossible => unreachable!(),
-                        e @ _ => {
-                            eprintln!("{e}{}", parser.get_err_line());
-                            has_failed = true;
-                        }
-                    }
+        for (p, v) in ast.iter().enumerate() {
+            if let MusicalValues::Label(label) = v {
+                if function_pointer.insert(label.name.clone(), p).is_some() {
+                    eprintln!("Warning: label '{}' is defined more than once", label.name);
                 }
             }
-            p += 1;
-        }
-        if has_failed {
-            return Err(CompilerError::Failed);
         }
         if !function_pointer.contains_key("main") {
             return Err(CompilerError::NoMain);
         }
         let variables = VariableType::new();
+        let timeline = Timeline::new();
         Ok(Self {
             ast,
+            timeline,
             variables,
+            label: 0,
             cursor: 0,
+            callers: vec![],
+            voice: 0,
+            transposition: 0,
+            random: Random::new(1),
+            parts: vec![],
+            samples: HashMap::new(),
+            soundfonts: HashMap::new(),
             function_pointer,
             audio_device,
+            sample_rate: None,
+            stream: None,
+            forget: false,
         })
     }
 
     fn load_global_variables(&mut self) {
-        let first_label = self
-            .function_pointer
-            .values()
-            .min()
-            .unwrap_or(&0usize)
-            .clone();
-        while self.cursor < first_label as usize {
+        for value in self.ast.iter() {
             let mut key = "".to_string();
-            match &self.ast[self.cursor] {
+            match value {
                 MusicalValues::Var(v) => {
                     key += &v.name;
-                    self.variables.global.insert(key, v.value);
+                    self.variables.global.insert(key, v.value.clone());
                 }
-                e @ _ => println!("Warning: ignoring instruction {e}"),
+                MusicalValues::Label(_) => (),
+                e @ _ => eprintln!("Warning: ignoring instruction {e}"),
             }
-            self.cursor += 1;
         }
     }
 
-    fn try_increase_cursor(&mut self) -> bool {
-        if self.ast.len() > self.cursor + 1 {
-            self.cursor += 1;
-            true
-        } else {
-            false
+    /// The statements of the label at `label` in the AST.
+    fn body(&self, label: usize) -> &[MusicalValues] {
+        match &self.ast[label] {
+            MusicalValues::Label(l) => &l.body,
+            _ => &[],
         }
     }
 
-    fn interpret_pair(&mut self, pair: (String, String)) -> Result<(), CompilerError> {
+    fn interpret_pair(
+        &mut self,
+        pair: (String, String),
+        args: Vec<f32>,
+    ) -> Result<(), CompilerError> {
         match (pair.0.to_lowercase().as_str(), pair.1) {
             ("goto", label) => {
-                if let Some(pointer) = self.function_pointer.get(&label) {
-                    // self.variables.scoped.clear();
-                    let last_scope_vars = self.variables.scoped.clone();
-                    self.variables.scoped.clear();
-                    let prevcursor = self.cursor;
-                    self.cursor = *pointer;
-                    self.try_increase_cursor();
-                    self.run_body()?;
-                    self.cursor = prevcursor;
-                    self.variables.scoped = last_scope_vars;
+                if let Some(pointer) = self.function_pointer.get(&label).copied() {
+                    self.call(pointer, &format!("goto {label}"), args);
                 } else {
                     eprintln!("Warning: ignoring 'goto {label}': label doesn't exist");
                 }
                 Ok(())
             }
-            ("inc", var) => {
-                if let Some(val) = self.variables.scoped.get(&var) {
-                    self.variables.scoped.insert(var, val + 1f32);
-                } else if let Some(val) = self.variables.global.get(&var) {
-                    self.variables.global.insert(var, *val + 1f32);
+            ("spawn", label) => {
+                if let Some(pointer) = self.function_pointer.get(&label).copied() {
+                    // the new voice starts where the current one is at.
+                    let parent = &self.timeline.voices[self.voice];
+                    let velocity = parent.velocity();
+                    let mut voice = Voice::new(&label, parent.time);
+                    voice.last_key = parent.last_key;
+                    voice.set_velocity(velocity, 0.0);
+                    self.timeline.voices.push(voice);
+                    // it goes first, the current one waiting for its turn
+                    let mut part = Part {
+                        label: pointer,
+                        cursor: 0,
+                        callers: vec![],
+                        voice: self.timeline.voices.len() - 1,
+                        transposition: self.transposition,
+                        scoped: HashMap::new(),
+                        random: Random::new(self.random.next()),
+                    };
+                    self.swap(&mut part);
+                    self.bind_arguments(pointer, &format!("spawn {label}"), args);
+                    self.parts.push(part);
                 } else {
-                    eprintln!("Warning: ignoring 'inc {var}': variable does not exist");
+                    eprintln!("Warning: ignoring 'spawn {label}': label doesn't exist");
                 }
                 Ok(())
             }
-            ("dec", var) => {
-                if let Some(val) = self.variables.scoped.get(&var) {
-                    self.variables.scoped.insert(var, val - 1f32);
-                } else if let Some(val) = self.variables.global.get(&var) {
-                    self.variables.global.insert(var, *val - 1f32);
+            ("transpose", label) => {
+                if let Some(pointer) = self.function_pointer.get(&label).copied() {
+                    // the rest of the arguments belong to the label
+                    let semitones = args.first().copied().unwrap_or(0.0);
+                    let args = args.into_iter().skip(1).collect();
+                    self.call(pointer, &format!("transpose {label}"), args);
+                    self.transposition += semitones.round() as i32;
                 } else {
-                    eprintln!("Warning: ignoring 'inc {var}': variable does not exist");
+                    eprintln!("Warning: ignoring 'transpose {label}': label doesn't exist");
+                }
+                Ok(())
+            }
+            (dir @ ("cresc" | "dim"), marking) => {
+                let Some(velocity) = dynamic_velocity(&marking) else {
+                    eprintln!("Warning: ignoring '{dir} {marking}': not a dynamic marking");
+                    return Ok(());
+                };
+                // beats, which are quarter notes
+                let beats = args.first().copied().unwrap_or(0.0);
+                self.timeline.voices[self.voice].set_velocity(velocity, beats / 4.0);
+                Ok(())
+            }
+            (op @ ("inc" | "dec"), var) => {
+                let step = if op == "inc" { 1.0 } else { -1.0 };
+                let variables = &mut self.variables;
+                match variables
+                    .scoped
+                    .get_mut(&var)
+                    .or(variables.global.get_mut(&var))
+                {
+                    Some(Value::Num(val)) => *val += step,
+                    Some(_) => return Err(CompilerError::NotANumber(var)),
+                    None => eprintln!("Warning: ignoring '{op} {var}': variable does not exist"),
+                }
+                Ok(())
+            }
+            ("rand", var) => {
+                let [min, max] = args[..] else {
+                    eprintln!("Warning: ignoring 'rand {var}': expected a minimum and a maximum");
+                    return Ok(());
+                };
+                let value =
+                    Value::Num(self.random.range(min.round() as i64, max.round() as i64) as f32);
+                // like `inc`, changes the global variable unless the label has its own
+                if !self.variables.scoped.contains_key(&var)
+                    && self.variables.global.contains_key(&var)
+                {
+                    self.variables.global.insert(var, value);
+                } else {
+                    self.variables.scoped.insert(var, value);
                 }
                 Ok(())
             }
             ("dbg", var) => {
                 if let Some(val) = self.variables.scoped.get(&var) {
-                    println!("SCOPED {var}: {val}")
+                    eprintln!("SCOPED {var}: {val}")
                 } else if let Some(val) = self.variables.global.get(&var) {
-                    println!("GLOBAL {var}: {val}");
+                    eprintln!("GLOBAL {var}: {val}");
                 } else {
-                    println!("VARIABLE {var} doesn't exist.");
+                    eprintln!("VARIABLE {var} doesn't exist.");
                 }
                 Ok(())
             }
@@ -195,39 +308,440 @@ impl<'a> Compiler {
         }
     }
 
-    fn run_body(&mut self) -> Result<(), CompilerError> {
-        match &self.ast[self.cursor] {
-            MusicalValues::Label(_) => {
-                return Ok(());
+    /// Goes to the label at `label`, which runs in a scope of its own until it
+    /// returns to where the current one is at.
+    fn call(&mut self, label: usize, call: &str, args: Vec<f32>) {
+        self.callers.push(Caller {
+            label: self.label,
+            cursor: self.cursor,
+            transposition: self.transposition,
+            scoped: mem::take(&mut self.variables.scoped),
+        });
+        self.bind_arguments(label, call, args);
+        self.label = label;
+        self.cursor = 0;
+    }
+
+    /// Returns to the label that went to the current one, `false` if there's none
+    /// and the voice is over.
+    fn ret(&mut self) -> bool {
+        let Some(caller) = self.callers.pop() else {
+            return false;
+        };
+        self.label = caller.label;
+        self.cursor = caller.cursor;
+        self.transposition = caller.transposition;
+        self.variables.scoped = caller.scoped;
+        true
+    }
+
+    /// Puts the voice being played into `part` and takes up the one it had.
+    fn swap(&mut self, part: &mut Part) {
+        mem::swap(&mut self.label, &mut part.label);
+        mem::swap(&mut self.cursor, &mut part.cursor);
+        mem::swap(&mut self.callers, &mut part.callers);
+        mem::swap(&mut self.voice, &mut part.voice);
+        mem::swap(&mut self.transposition, &mut part.transposition);
+        mem::swap(&mut self.variables.scoped, &mut part.scoped);
+        mem::swap(&mut self.random, &mut part.random);
+    }
+
+    /// Which of the waiting voices is the furthest behind, the first one of those
+    /// that are as far.
+    fn furthest_behind(&self) -> Option<usize> {
+        let time = |i: &usize| self.timeline.voices[self.parts[*i].voice].time;
+        (0..self.parts.len()).min_by(|a, b| time(a).total_cmp(&time(b)))
+    }
+
+    /// Binds the arguments of a call to the parameters of the label at `label`
+    /// as scoped variables.
+    fn bind_arguments(&mut self, label: usize, call: &str, args: Vec<f32>) {
+        let params = match &self.ast[label] {
+            MusicalValues::Label(l) => l.params.clone(),
+            _ => vec![],
+        };
+        if args.len() > params.len() {
+            eprintln!(
+                "Warning: '{call}' takes {} argument(s) but {} were given",
+                params.len(),
+                args.len()
+            );
+        }
+        for (i, param) in params.into_iter().enumerate() {
+            if let Some(value) = args.get(i) {
+                self.variables.scoped.insert(param, Value::Num(*value));
+            } else {
+                eprintln!("Warning: '{call}' is missing argument '{param}'");
+            }
+        }
+    }
+
+    /// The MIDI key of a note in `octave`, `last_key` being the key of the note before it.
+    fn resolve_key(
+        &self,
+        note: &Note,
+        octave: f32,
+        last_key: Option<i32>,
+    ) -> Result<i32, CompilerError> {
+        let c = 12 * (octave as i32 + 1);
+        // intervals follow the note before, which is already transposed
+        let transpose = match note.pitch {
+            Pitch::Interval(_) => 0,
+            _ => self.transposition + self.variables.get_or("transpose", 0.0)?.round() as i32,
+        };
+        let key = match &note.pitch {
+            Pitch::Named(name, modifier) => c + name.semitone() + modifier.semitones(),
+            Pitch::Degree(degree) => {
+                let degree = match degree {
+                    Degree::Num(n) => *n,
+                    Degree::Var(var) => self.variables.get(var)?.round() as i32,
+                };
+                let tonic = match self.variables.get_value("key") {
+                    None => 0,
+                    Some(v) => note_semitone(&v.to_string())
+                        .ok_or(CompilerError::UnknownKey(v.to_string()))?,
+                };
+                let steps = match self.variables.get_value("mode") {
+                    None => mode_steps("major").unwrap(),
+                    Some(v) => mode_steps(&v.to_string())
+                        .ok_or(CompilerError::UnknownMode(v.to_string()))?,
+                };
+                // ^1 is the tonic, ^8 the tonic an octave higher and ^0 the 7th below it.
+                let step = degree - 1;
+                c + tonic + steps[step.rem_euclid(7) as usize] + 12 * step.div_euclid(7)
+            }
+            Pitch::Interval(interval) => last_key.unwrap_or(c) + interval,
+        };
+        Ok(key + note.shift + transpose)
+    }
+
+    /// The swing and humanize settings.
+    fn timing(&self) -> Result<Timing, CompilerError> {
+        let swing = self.variables.get_or("swing", 50.0)?;
+        if swing <= 0.0 || swing >= 100.0 {
+            return Err(CompilerError::OutOfRange("swing".into(), 0.0, 100.0));
+        }
+        let humanize = self.variables.get_or("humanize", 0.0)?.max(0.0);
+        Ok(Timing {
+            swing: swing / 100.0,
+            humanize: humanize / 1000.0,
+        })
+    }
+
+    /// The effects set by variables, whose names start with `prefix`.
+    fn effects(&self, prefix: &str) -> Result<Effects, CompilerError> {
+        let get =
+            |name: &str, default: f32| self.variables.get_or(&format!("{prefix}{name}"), default);
+        // milliseconds and percent
+        Ok(Effects {
+            lowpass: get("lowpass", 0.0)?,
+            highpass: get("highpass", 0.0)?,
+            delay: get("delay", 0.0)? / 1000.0,
+            feedback: get("feedback", 30.0)? / 100.0,
+            reverb: get("reverb", 0.0)? / 100.0,
+            room: get("room", 50.0)? / 100.0,
+            gain: get("gain", 0.0)?,
+            pan: get("pan", 0.0)?.clamp(-50.0, 50.0) / 50.0,
+        })
+    }
+
+    /// The arpeggio set by the `arp` variable and how often it plays a note.
+    fn arpeggio(&self) -> Result<Option<(Arpeggio, f32)>, CompilerError> {
+        let arp = match self.variables.get_value("arp") {
+            None => return Ok(None),
+            Some(Value::Ident(name)) if name == "off" => return Ok(None),
+            Some(v) => Arpeggio::from_name(&v.to_string())
+                .ok_or(CompilerError::UnknownArpeggio(v.to_string()))?,
+        };
+        let rate = self.variables.get_or("arp_rate", 1.0 / 16.0)?;
+        if rate <= 0.0 {
+            return Err(CompilerError::NotPositive("arp_rate".into()));
+        }
+        Ok(Some((arp, rate)))
+    }
+
+    /// The instrument set by the `instrument` variable, a sine if there's none.
+    fn instrument(&mut self) -> Result<Instrument, CompilerError> {
+        let waveform = match self.variables.get_value("instrument") {
+            None => Waveform::Sine,
+            Some(Value::Ident(name)) if name == "drums" => return Ok(Instrument::Drums),
+            Some(Value::Source(Source::Sample { path, root, looped })) => {
+                let (path, root, looped) = (path.clone(), *root, *looped);
+                let envelope = self.envelope()?;
+                return Ok(Instrument::Sample {
+                    sample: self.sample(&path)?,
+                    root,
+                    looped,
+                    envelope,
+                });
+            }
+            Some(Value::Source(Source::SoundFont {
+                path,
+                program,
+                bank,
+            })) => {
+                let (path, program, bank) = (path.clone(), *program, *bank);
+                let font = self.soundfont(&path)?;
+                let preset = font
+                    .preset(bank, program)
+                    .ok_or(CompilerError::NoPreset(path, bank, program))?;
+                return Ok(Instrument::SoundFont { font, preset });
+            }
+            Some(Value::Ident(name)) => {
+                let width = self.variables.get_or("pulse_width", 50.0)? / 100.0;
+                Waveform::from_name(name, width)
+                    .ok_or(CompilerError::UnknownInstrument(name.clone()))?
+            }
+            Some(v) => return Err(CompilerError::UnknownInstrument(v.to_string())),
+        };
+        let envelope = self.envelope()?;
+        Ok(Instrument::Synth { waveform, envelope })
+    }
+
+    /// The envelope set by the `attack`, `decay`, `sustain` and `release` variables.
+    fn envelope(&self) -> Result<Envelope, CompilerError> {
+        // milliseconds and percent
+        Ok(Envelope {
+            attack: self.variables.get_or("attack", 5.0)? / 1000.0,
+            decay: self.variables.get_or("decay", 0.0)? / 1000.0,
+            sustain: self.variables.get_or("sustain", 100.0)?.clamp(0.0, 100.0) / 100.0,
+            release: self.variables.get_or("release", 20.0)? / 1000.0,
+        })
+    }
+
+    /// The WAV file at `path`, which is only read the first time it's used.
+    fn sample(&mut self, path: &str) -> Result<Arc<Sample>, CompilerError> {
+        if let Some(sample) = self.samples.get(path) {
+            return Ok(sample.clone());
+        }
+        let sample = Sample::read_wav(Path::new(path))
+            .map_err(|e| CompilerError::LoadFailed(path.into(), e.to_string()))?;
+        let sample = Arc::new(sample);
+        self.samples.insert(path.into(), sample.clone());
+        Ok(sample)
+    }
+
+    /// The SF2 file at `path`, which is only read the first time it's used.
+    fn soundfont(&mut self, path: &str) -> Result<Arc<SoundFont>, CompilerError> {
+        if let Some(font) = self.soundfonts.get(path) {
+            return Ok(font.clone());
+        }
+        let font = SoundFont::read(Path::new(path))
+            .map_err(|e| CompilerError::LoadFailed(path.into(), e.to_string()))?;
+        let font = Arc::new(font);
+        self.soundfonts.insert(path.into(), font.clone());
+        Ok(font)
+    }
+
+    /// Adds `chord` to the current voice.
+    fn play(&mut self, chord: &Chord) -> Result<(), CompilerError> {
+        let octave = self.variables.get("octave")?;
+        let legato = self.variables.get_or("legato", 100.0)? / 100.0;
+        let velocity = self.timeline.voices[self.voice].velocity();
+        let duration = chord.held_duration();
+        let mut last_key = self.timeline.voices[self.voice].last_key;
+        // (key, velocity, fraction of the duration it sounds for)
+        let mut notes = vec![];
+        for n in chord.notes.iter() {
+            let key = self.resolve_key(n, octave, last_key)?;
+            last_key = Some(key);
+            notes.push((key, n.velocity(velocity), n.length(1.0, legato)));
+        }
+        let tones = match self.arpeggio()? {
+            Some((arp, rate)) if notes.len() > 1 => {
+                arp.tones(&notes, duration, rate, &mut self.random)
+            }
+            _ => notes
+                .into_iter()
+                .map(|(key, velocity, legato)| Tone {
+                    key,
+                    velocity,
+                    length: duration * legato,
+                    offset: 0.0,
+                })
+                .collect(),
+        };
+        let instrument = self.instrument()?;
+        let timing = self.timing()?;
+        let effects = self.effects("")?;
+        let voice = &mut self.timeline.voices[self.voice];
+        voice.last_key = last_key;
+        let lyric = chord.lyric.clone();
+        voice.push(tones, duration, instrument, timing, lyric, effects);
+        Ok(())
+    }
+
+    /// Adds the drums of `hit` to the current voice.
+    fn hit(&mut self, hit: &Hit) -> Result<(), CompilerError> {
+        let voice = &self.timeline.voices[self.voice];
+        let velocity = voice.velocity();
+        let tones = hit
+            .drums
+            .iter()
+            .map(|drum| Tone {
+                key: drum.key(),
+                velocity,
+                length: hit.duration,
+                offset: 0.0,
+            })
+            .collect();
+        let timing = self.timing()?;
+        let effects = self.effects("")?;
+        let voice = &mut self.timeline.voices[self.voice];
+        voice.push(
+            tones,
+            hit.duration,
+            Instrument::Drums,
+            timing,
+            None,
+            effects,
+        );
+        Ok(())
+    }
+
+    /// Runs the next statement of the voice being played, `false` once its label
+    /// ends or hits `ret` and there's nothing to return to.
+    fn step(&mut self) -> Result<bool, CompilerError> {
+        let Some(value) = self.body(self.label).get(self.cursor) else {
+            return Ok(self.ret());
+        };
+        match value {
+            MusicalValues::Label(_) | MusicalValues::Ret => return Ok(self.ret()),
+            MusicalValues::Pair(p, args) => {
+                let (p, args) = (p.clone(), args.clone());
+                // a `goto` that's the last thing in the label carries on in the
+                // label it goes to instead of coming back, so labels can loop forever
+                if let Some(pointer) = self.tail_call(&p) {
+                    self.variables.scoped.clear();
+                    self.bind_arguments(pointer, &format!("goto {}", p.1), args);
+                    self.label = pointer;
+                    self.cursor = 0;
+                    return Ok(true);
+                }
+                // labels it goes to return to what's after it
+                self.cursor += 1;
+                self.interpret_pair(p, args)?;
+                return Ok(true);
             }
-            MusicalValues::Pair(p) => self.interpret_pair(p.clone())?,
-            MusicalValues::Chord(chord) => {
-                let base_pitch = self.variables.get_global("pitch")?;
-                let octave = self.variables.get_global("octave")?;
-                play(chord, base_pitch, octave);
+            MusicalValues::Chord(chord) => self.play(&chord.clone())?,
+            MusicalValues::Choose(chords) => {
+                let chords = chords.clone();
+                let i = self.random.range(0, chords.len() as i64 - 1) as usize;
+                self.play(&chords[i])?;
             }
             MusicalValues::Var(v) => {
-                self.variables.scoped.insert(v.name.clone(), v.value);
+                self.variables
+                    .scoped
+                    .insert(v.name.clone(), v.value.clone());
             }
+            MusicalValues::Hit(hit) => self.hit(&hit.clone())?,
+            MusicalValues::Marker(name) => {
+                let name = name.clone();
+                let voice = &mut self.timeline.voices[self.voice];
+                voice.markers.push((voice.time, name));
+            }
+            MusicalValues::Dynamic(marking) => {
+                if let Some(velocity) = dynamic_velocity(marking) {
+                    self.timeline.voices[self.voice].set_velocity(velocity, 0.0);
+                }
+            }
+            MusicalValues::Include(_) | MusicalValues::Import(_) => (),
+        }
+        // dbg!(&self.variables);
+        self.cursor += 1;
+        Ok(true)
+    }
+
+    /// The label that `pair` goes to if it's a `goto` with nothing after it in the
+    /// label being run.
+    fn tail_call(&self, pair: &(String, String)) -> Option<usize> {
+        let last = matches!(
+            self.body(self.label).get(self.cursor + 1),
+            None | Some(MusicalValues::Label(_) | MusicalValues::Ret)
+        );
+        if !last || pair.0.to_lowercase() != "goto" {
+            return None;
         }
-        if !self.try_increase_cursor() {
+        self.function_pointer.get(&pair.1).copied()
+    }
+
+    /// Lets the stream play what's been played so far, which is up to where the
+    /// voice that's the furthest behind is at.
+    fn advance(&mut self) -> Result<(), CompilerError> {
+        let Some(stream) = &mut self.stream else {
             return Ok(());
+        };
+        let playing: Vec<usize> = self.parts.iter().map(|p| p.voice).collect();
+        let playing = [&playing[..], &[self.voice]].concat();
+        let voices = &self.timeline.voices;
+        let time = playing
+            .iter()
+            .map(|v| voices[*v].time)
+            .fold(f32::MAX, f32::min);
+        stream
+            .advance(&self.timeline, time)
+            .map_err(|e| CompilerError::WriteFailed(e.to_string()))?;
+        if self.forget {
+            let dropped = stream.forget(&mut self.timeline, &playing);
+            // the voices after those that were dropped move down
+            let renumber = |v: usize| v - dropped.iter().filter(|d| **d < v).count();
+            self.voice = renumber(self.voice);
+            for part in self.parts.iter_mut() {
+                part.voice = renumber(part.voice);
+            }
         }
-        // dbg!(&self.variables);
-        self.run_body()
+        Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), CompilerError> {
         // println!("{:#?}", self.ast);
         self.load_global_variables();
+        for setting in ["bpm", "pitch"] {
+            if self.variables.get_global(setting)? <= 0.0 {
+                return Err(CompilerError::NotPositive(setting.into()));
+            }
+        }
+        self.timeline.bpm = self.variables.get_global("bpm")?;
+        self.timeline.pitch = self.variables.get_global("pitch")?;
+        let seed = self.variables.get_or("seed", 1.0)? as u32;
+        self.random = Random::new(seed);
+        self.timeline.seed = seed;
+        self.timeline.master = self.effects("master_")?;
+        self.timeline.channels = match self.variables.get_or("channels", 2.0)? {
+            1.0 => 1,
+            2.0 => 2,
+            _ => return Err(CompilerError::OutOfRange("channels".into(), 1.0, 2.0)),
+        };
+        let rate = self.variables.get_or("samplerate", SAMPLE_RATE as f32)?;
+        if !(8000.0..=192000.0).contains(&rate) {
+            return Err(CompilerError::OutOfRange(
+                "samplerate".into(),
+                8000.0,
+                192000.0,
+            ));
+        }
+        self.timeline.sample_rate = self.sample_rate.unwrap_or(rate as u32);
         // set instruction pointer to main function's start.
-        self.cursor = self.function_pointer.get("main").unwrap().clone();
-        if !self.try_increase_cursor() {
-            // main function is empty
-            return Ok(());
+        self.label = *self.function_pointer.get("main").unwrap();
+        loop {
+            let playing = self.step()?;
+            let time = self.timeline.voices[self.voice].time;
+            // the voice that's the furthest behind goes on, this one unless it's over
+            // or ahead of another
+            let next = self
+                .furthest_behind()
+                .filter(|i| !playing || self.timeline.voices[self.parts[*i].voice].time < time);
+            if let Some(next) = next {
+                let mut part = self.parts.remove(next);
+                self.swap(&mut part);
+                if playing {
+                    self.parts.push(part);
+                }
+            } else if !playing {
+                return Ok(());
+            }
+            self.advance()?;
         }
-        self.run_body()?;
-        Ok(())
     }
 }
 
@@ -237,6 +751,18 @@ pub enum CompilerError {
     NoMain,
     NoFunc(String),
     GlobalPropertyMissing(String),
+    ReadFailed(String),
+    IncludeCycle(String),
+    NotANumber(String),
+    UnknownInstrument(String),
+    UnknownKey(String),
+    UnknownMode(String),
+    UnknownArpeggio(String),
+    NotPositive(String),
+    OutOfRange(String, f32, f32),
+    LoadFailed(String, String),
+    WriteFailed(String),
+    NoPreset(String, u16, u16),
 }
 
 impl Error for CompilerError {}
@@ -250,20 +776,185 @@ impl Display for CompilerError {
             Self::GlobalPropertyMissing(s) => {
                 write!(f, "Global property '{s}' is missing, but is required")
             }
+            Self::ReadFailed(s) => write!(f, "Couldn't read '{s}'"),
+            Self::IncludeCycle(s) => write!(f, "'{s}' is included in a cycle"),
+            Self::NotANumber(s) => write!(f, "'{s}' has to be a number"),
+            Self::UnknownInstrument(s) => write!(f, "Unknown instrument '{s}'"),
+            Self::UnknownKey(s) => write!(f, "Unknown key '{s}'"),
+            Self::UnknownMode(s) => write!(f, "Unknown mode '{s}'"),
+            Self::UnknownArpeggio(s) => write!(f, "Unknown arpeggio '{s}'"),
+            Self::NotPositive(s) => write!(f, "'{s}' has to be above 0"),
+            Self::OutOfRange(s, min, max) => write!(f, "'{s}' has to be between {min} and {max}"),
+            Self::LoadFailed(s, e) => write!(f, "Couldn't load '{s}': {e}"),
+            Self::WriteFailed(e) => write!(f, "Couldn't write the audio: {e}"),
+            Self::NoPreset(s, bank, program) => {
+                write!(
+                    f,
+                    "'{s}' has no preset with program {program} in bank {bank}"
+                )
+            }
         }
     }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let shit = fs::read_to_string("test.musical")?;
-    let compiler = Compiler::new(&shit);
+    let args = match CompilerArgs::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let path = Path::new(&args.filename);
+    let shit = fs::read_to_string(path)?;
+    let compiler = Compiler::new(&shit, path);
     if let Err(e) = compiler {
         eprintln!("{e}");
         exit(1);
     }
     let mut compiler = compiler.unwrap();
+    compiler.sample_rate = args.sample_rate;
+    let output = match &args.output {
+        Some(output) => match Format::new(args.format.as_deref(), Path::new(output)) {
+            Ok(format) => Some((Path::new(output), format)),
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    let save = output.filter(|(_, f)| !matches!(f, Format::Raw(_)));
+    compiler.forget = save.is_none();
+    // raw audio is streamed instead of being played
+    if let Some((path, Format::Raw(encoding))) = output {
+        let writer = export::raw::Writer::new(export::create(path)?, encoding);
+        compiler.stream = Some(Stream::new(Box::new(writer)));
+    } else if args.run {
+        match audio::device(args.device.as_deref(), args.lookahead) {
+            Ok(sink) => {
+                let clock = Box::new(SystemClock::default());
+                compiler.stream = Some(Stream::live(sink, clock, args.lookahead));
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                // without anywhere to play or save it, running would do nothing
+                if save.is_none() {
+                    exit(1);
+                }
+            }
+        }
+    }
     if let Err(e) = compiler.run() {
         eprintln!("Error: {e}");
+        exit(1);
+    }
+    if let Some(stream) = compiler.stream.take() {
+        stream.finish(&compiler.timeline)?;
+    }
+    if let Some((path, format)) = save {
+        export::save(&compiler.timeline, path, format)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, io, rc::Rc};
+
+    /// The notes of every voice of `program` once it's run.
+    fn run(program: &str) -> String {
+        let mut compiler = Compiler::new(program, Path::new("test.musical")).unwrap();
+        compiler.run().unwrap();
+        format!("{:?}", compiler.timeline.voices)
+    }
+
+    #[test]
+    fn seed_reproduces_random_choices() {
+        let body = "rand n 1 7\n^n 1/8\nchoose { C 1/8; E 1/8; G 1/8 }\n".repeat(8);
+        let program = |seed: u32| {
+            format!("bpm: 120\npitch: 440\noctave: 4\nseed: {seed}\nn: 1\n@main {{\n{body}}}\n")
+        };
+        assert_eq!(run(&program(7)), run(&program(7)));
+        assert_ne!(run(&program(7)), run(&program(8)));
+    }
+
+    /// Keeps what's written to it where the test can read it.
+    struct Capture(Rc<RefCell<Vec<f32>>>);
+
+    impl audio::Sink for Capture {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streaming_renders_the_same_as_rendering_at_once() {
+        // humanize over a beat is held to a beat, which the stream leaves room for
+        let body = "C 1/8\nE 1/8\nG 1/4\nswing: 66\nC 1/8\nD 1/8\n".repeat(16);
+        let program =
+            format!("bpm: 120\npitch: 440\noctave: 4\nhumanize: 2000\n@main {{\n{body}}}\n");
+        let mut compiler = Compiler::new(&program, Path::new("test.musical")).unwrap();
+        let captured = Rc::new(RefCell::new(vec![]));
+        compiler.stream = Some(Stream::new(Box::new(Capture(captured.clone()))));
+        compiler.run().unwrap();
+        let stream = compiler.stream.take().unwrap();
+        stream.finish(&compiler.timeline).unwrap();
+        assert_eq!(*captured.borrow(), synth::render(&compiler.timeline));
+    }
+
+    #[test]
+    fn streaming_forgets_what_has_been_played() {
+        let body = "spawn arp\nC 1/4\nG 1/4\n".repeat(16);
+        let program = format!(
+            "bpm: 240\npitch: 440\noctave: 4\nhumanize: 20\n\
+             @arp {{\nE 1/8\nG 1/8\nB 1/8\n}}\n@main {{\n{body}}}\n"
+        );
+        let mut batch = Compiler::new(&program, Path::new("test.musical")).unwrap();
+        batch.run().unwrap();
+
+        let mut compiler = Compiler::new(&program, Path::new("test.musical")).unwrap();
+        let captured = Rc::new(RefCell::new(vec![]));
+        compiler.stream = Some(Stream::new(Box::new(Capture(captured.clone()))));
+        compiler.forget = true;
+        compiler.run().unwrap();
+        // only what's still to be played is left
+        assert!(compiler.timeline.voices.len() < 4);
+        assert!(compiler.timeline.voices[0].events.len() < 8);
+        let stream = compiler.stream.take().unwrap();
+        stream.finish(&compiler.timeline).unwrap();
+        assert_eq!(*captured.borrow(), synth::render(&batch.timeline));
+    }
+
+    /// Like `Capture`, but stops taking samples once it has `1` of them.
+    struct Full(Rc<RefCell<Vec<f32>>>, usize);
+
+    impl audio::Sink for Full {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            let mut captured = self.0.borrow_mut();
+            if captured.len() >= self.1 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "full"));
+            }
+            captured.extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spawned_voices_can_loop_forever() {
+        let program = "bpm: 120\npitch: 440\noctave: 4\n\
+                       @drums {\nE 1/4\ngoto drums\n}\n@main {\nspawn drums\nC 1/4\n}\n";
+        let mut compiler = Compiler::new(program, Path::new("test.musical")).unwrap();
+        let captured = Rc::new(RefCell::new(vec![]));
+        // four seconds, main's note is over after half a second
+        let rate = SAMPLE_RATE as usize;
+        compiler.stream = Some(Stream::new(Box::new(Full(captured.clone(), rate * 8))));
+        compiler.forget = true;
+        assert!(matches!(compiler.run(), Err(CompilerError::WriteFailed(_))));
+        assert!(compiler.timeline.voices.len() <= 2);
+        let captured = captured.borrow();
+        assert!(captured[rate * 2..rate * 8].iter().any(|s| s.abs() > 0.01));
+    }
+}
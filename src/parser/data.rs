@@ -1,25 +1,204 @@
-#[derive(Debug)]
+use core::fmt;
+
+use crate::parser::parser::MusicalValues;
+
+#[derive(Debug, Clone)]
 #[rustfmt::skip]
 pub enum NoteModifier { Flat, Sharp, None }
 
-#[derive(Debug)]
+impl NoteModifier {
+    pub fn semitones(&self) -> i32 {
+        match self {
+            Self::Flat => -1,
+            Self::Sharp => 1,
+            Self::None => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 #[rustfmt::skip]
 pub enum NoteName { A, B, C, D, E, F, G }
 
 impl NoteName {
-    pub fn get_freq(&self, base: &f32) {}
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'A' => Some(Self::A),
+            'B' | 'H' => Some(Self::B),
+            'C' => Some(Self::C),
+            'D' => Some(Self::D),
+            'E' => Some(Self::E),
+            'F' => Some(Self::F),
+            'G' => Some(Self::G),
+            _ => None,
+        }
+    }
+
+    /// Semitones above C.
+    pub fn semitone(&self) -> i32 {
+        match self {
+            Self::C => 0,
+            Self::D => 2,
+            Self::E => 4,
+            Self::F => 5,
+            Self::G => 7,
+            Self::A => 9,
+            Self::B => 11,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Articulation {
+    /// `.`, sounds for half of its duration.
+    Staccato,
+    /// `'`, played louder.
+    Accent,
+    /// `_`, sounds for all of its duration.
+    Tenuto,
+    /// `~`, held for twice its duration.
+    Fermata,
+}
+
+impl Articulation {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '.' => Some(Self::Staccato),
+            '\'' => Some(Self::Accent),
+            '_' => Some(Self::Tenuto),
+            '~' => Some(Self::Fermata),
+            _ => None,
+        }
+    }
+}
+
+/// A scale degree, `^3` or `^var`.
+#[derive(Debug, Clone)]
+pub enum Degree {
+    Num(i32),
+    Var(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pitch {
+    Named(NoteName, NoteModifier),
+    /// a degree of the current key and mode.
+    Degree(Degree),
+    /// `+7` or `-2`, semitones away from the previous note.
+    Interval(i32),
+}
+
+#[derive(Debug, Clone)]
 pub struct Note {
-    pub note: NoteName,
-    pub modifier: NoteModifier,
+    pub pitch: Pitch,
+    /// semitones added to the note, used by chord symbols.
+    pub shift: i32,
+    pub articulations: Vec<Articulation>,
+    /// overrides the dynamic the note is played at.
+    pub velocity: Option<u8>,
 }
 
-#[derive(Debug)]
+impl Note {
+    pub fn new(pitch: Pitch) -> Self {
+        Self {
+            pitch,
+            shift: 0,
+            articulations: vec![],
+            velocity: None,
+        }
+    }
+
+    /// Semitones above the C of its octave, which is negative for Cb. Only named
+    /// notes have one, the interpreter resolves the others.
+    pub fn semitone(&self) -> Option<i32> {
+        match &self.pitch {
+            Pitch::Named(note, modifier) => Some(note.semitone() + modifier.semitones()),
+            _ => None,
+        }
+    }
+
+    /// How long the note sounds for when it's played for `duration`, `legato`
+    /// being the fraction of it notes without an articulation sound for.
+    pub fn length(&self, duration: f32, legato: f32) -> f32 {
+        if self.articulations.contains(&Articulation::Staccato) {
+            duration / 2.0
+        } else if self.articulations.contains(&Articulation::Tenuto) {
+            duration
+        } else {
+            duration * legato
+        }
+    }
+
+    /// The velocity of the note at the given dynamic.
+    pub fn velocity(&self, dynamic: u8) -> u8 {
+        let velocity = self.velocity.unwrap_or(dynamic);
+        if self.articulations.contains(&Articulation::Accent) {
+            velocity.saturating_add(24).min(127)
+        } else {
+            velocity
+        }
+    }
+}
+
+/// Semitones above C of a note written as text, like the `Eb` in `key: Eb`.
+pub fn note_semitone(name: &str) -> Option<i32> {
+    let mut chars = name.chars();
+    let note = NoteName::from_char(chars.next()?)?;
+    let modifier = match chars.as_str() {
+        "" => NoteModifier::None,
+        "#" => NoteModifier::Sharp,
+        "b" => NoteModifier::Flat,
+        _ => return None,
+    };
+    Some(note.semitone() + modifier.semitones())
+}
+
+/// Semitones above the tonic of each degree of a mode.
+pub fn mode_steps(mode: &str) -> Option<[i32; 7]> {
+    match mode.to_lowercase().as_str() {
+        "major" | "ionian" => Some([0, 2, 4, 5, 7, 9, 11]),
+        "dorian" => Some([0, 2, 3, 5, 7, 9, 10]),
+        "phrygian" => Some([0, 1, 3, 5, 7, 8, 10]),
+        "lydian" => Some([0, 2, 4, 6, 7, 9, 11]),
+        "mixolydian" => Some([0, 2, 4, 5, 7, 9, 10]),
+        "minor" | "aeolian" => Some([0, 2, 3, 5, 7, 8, 10]),
+        "locrian" => Some([0, 1, 3, 5, 6, 8, 10]),
+        _ => None,
+    }
+}
+
+/// Chord symbol suffixes and the semitones of their notes above the root. Longer
+/// suffixes come first so that `m7` isn't read as `m`.
+pub const CHORD_QUALITIES: &[(&str, &[i32])] = &[
+    ("maj7", &[0, 4, 7, 11]),
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("maj", &[0, 4, 7]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("min", &[0, 3, 7]),
+    ("m6", &[0, 3, 7, 9]),
+    ("m7", &[0, 3, 7, 10]),
+    ("m9", &[0, 3, 7, 10, 14]),
+    ("m", &[0, 3, 7]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("+", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("sus", &[0, 5, 7]),
+    ("M7", &[0, 4, 7, 11]),
+    ("M", &[0, 4, 7]),
+    ("6", &[0, 4, 7, 9]),
+    ("7", &[0, 4, 7, 10]),
+    ("9", &[0, 4, 7, 10, 14]),
+];
+
+#[derive(Debug, Clone)]
 pub struct Chord {
     pub notes: Vec<Note>,
     pub duration: f32,
+    /// the syllable sung on the chord, `C 1/4 "la"`.
+    pub lyric: Option<String>,
 }
 
 impl Chord {
@@ -27,10 +206,172 @@ impl Chord {
     pub fn is_note(&self) -> bool {
         self.notes.len() == 1
     }
+
+    /// How long the chord lasts, which a fermata on any of its notes doubles.
+    pub fn held_duration(&self) -> f32 {
+        let fermata = self
+            .notes
+            .iter()
+            .any(|n| n.articulations.contains(&Articulation::Fermata));
+        if fermata {
+            self.duration * 2.0
+        } else {
+            self.duration
+        }
+    }
+}
+
+/// A drum of the built-in kit, played with `kick 1/4`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Drum {
+    Kick,
+    Rim,
+    Snare,
+    Clap,
+    Hat,
+    OpenHat,
+    Tom,
+    Crash,
+    Ride,
+    Cowbell,
+}
+
+impl Drum {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kick" => Some(Self::Kick),
+            "rim" => Some(Self::Rim),
+            "snare" => Some(Self::Snare),
+            "clap" => Some(Self::Clap),
+            "hat" => Some(Self::Hat),
+            "openhat" => Some(Self::OpenHat),
+            "tom" => Some(Self::Tom),
+            "crash" => Some(Self::Crash),
+            "ride" => Some(Self::Ride),
+            "cowbell" => Some(Self::Cowbell),
+            _ => None,
+        }
+    }
+
+    /// Its key in the General MIDI percussion map.
+    pub fn key(&self) -> i32 {
+        match self {
+            Self::Kick => 36,
+            Self::Rim => 37,
+            Self::Snare => 38,
+            Self::Clap => 39,
+            Self::Hat => 42,
+            Self::Tom => 45,
+            Self::OpenHat => 46,
+            Self::Crash => 49,
+            Self::Ride => 51,
+            Self::Cowbell => 56,
+        }
+    }
+
+    /// The drum closest to a key of the General MIDI percussion map.
+    pub fn from_key(key: i32) -> Option<Self> {
+        match key {
+            35 | 36 => Some(Self::Kick),
+            37 => Some(Self::Rim),
+            38 | 40 => Some(Self::Snare),
+            39 => Some(Self::Clap),
+            42 | 44 => Some(Self::Hat),
+            41 | 43 | 45 | 47 | 48 | 50 => Some(Self::Tom),
+            46 => Some(Self::OpenHat),
+            49 | 52 | 55 | 57 => Some(Self::Crash),
+            51 | 53 | 59 => Some(Self::Ride),
+            56 => Some(Self::Cowbell),
+            _ => None,
+        }
+    }
+}
+
+/// Drums hit together, `kick hat 1/8`.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub drums: Vec<Drum>,
+    pub duration: f32,
+}
+
+/// MIDI velocity of a dynamic marking such as `mf`.
+pub fn dynamic_velocity(marking: &str) -> Option<u8> {
+    match marking {
+        "ppp" => Some(16),
+        "pp" => Some(33),
+        "p" => Some(49),
+        "mp" => Some(64),
+        "mf" => Some(80),
+        "f" => Some(96),
+        "ff" => Some(112),
+        "fff" => Some(127),
+        _ => None,
+    }
+}
+
+/// An instrument read from a file.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// `sample "piano_c4.wav" root C4 loop`, `root` being the MIDI key it was
+    /// recorded at.
+    Sample {
+        path: String,
+        root: i32,
+        looped: bool,
+    },
+    /// `sf2 "GeneralUser.sf2" program 0 bank 0`
+    SoundFont {
+        path: String,
+        program: u16,
+        bank: u16,
+    },
+}
+
+impl Source {
+    pub fn path_mut(&mut self) -> &mut String {
+        match self {
+            Self::Sample { path, .. } | Self::SoundFont { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Num(f32),
+    Ident(String),
+    Source(Source),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Ident(s) => write!(f, "{s}"),
+            Self::Source(Source::Sample { path, root, looped }) => {
+                write!(f, "sample \"{path}\" root {root}")?;
+                if *looped {
+                    write!(f, " loop")?;
+                }
+                Ok(())
+            }
+            Self::Source(Source::SoundFont {
+                path,
+                program,
+                bank,
+            }) => write!(f, "sf2 \"{path}\" program {program} bank {bank}"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Variable {
     pub name: String,
-    pub value: f32,
+    pub value: Value,
+}
+
+#[derive(Debug)]
+pub struct Label {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<MusicalValues>,
 }
@@ -5,7 +5,12 @@ pub enum ParseResponse {
     Int(usize),
     EOL(usize),
     Ident(usize),
+    String(usize),
     Unexpected(usize),
+    Block(usize),
+    NestedLabel(usize),
+    Choose(usize),
+    Note(usize),
     NotPossible,
     Done,
 }
@@ -19,7 +24,12 @@ impl fmt::Display for ParseResponse {
             E::Int(l) => write!(f, "Line {l}: Expected an unsigned number"),
             E::EOL(l) => write!(f, "Line {l}: Expected end of line"),
             E::Ident(l) => write!(f, "Line {l}: Expected an identifier"),
+            E::String(l) => write!(f, "Line {l}: Expected a string"),
             E::Unexpected(l) => write!(f, "Line {l}: Unexpected character(s)"),
+            E::Block(l) => write!(f, "Line {l}: Expected '}}' to close the label"),
+            E::NestedLabel(l) => write!(f, "Line {l}: Labels can't be defined inside of a label"),
+            E::Choose(l) => write!(f, "Line {l}: Expected chords inside of 'choose'"),
+            E::Note(l) => write!(f, "Line {l}: Expected a note and its octave, like C4"),
             E::Done => write!(f, ""),
             E::NotPossible => unreachable!(),
         }
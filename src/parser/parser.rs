@@ -1,22 +1,47 @@
 use core::fmt;
 
-use crate::parser::data::{Chord, Note, NoteModifier, NoteName, Variable};
+use crate::parser::data::{
+    dynamic_velocity, note_semitone, Articulation, Chord, Degree, Drum, Hit, Label, Note,
+    NoteModifier, NoteName, Pitch, Source, Value, Variable, CHORD_QUALITIES,
+};
 use crate::parser::error::ParseResponse;
 #[derive(Debug)]
 pub enum MusicalValues {
-    Label(String),
+    Label(Label),
     Chord(Chord),
     Var(Variable),
-    Pair((String, String)),
+    Pair((String, String), Vec<f32>),
+    Ret,
+    Include(String),
+    Import(String),
+    Dynamic(String),
+    /// `choose { ... }`, plays one of the chords at random.
+    Choose(Vec<Chord>),
+    /// `marker "Chorus"`, names a point of the piece.
+    Marker(String),
+    Hit(Hit),
 }
 
 impl fmt::Display for MusicalValues {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Label(s) => write!(f, "@{s}"),
-            Self::Pair(p) => write!(f, "pair '{} {}'", p.0, p.1),
+            Self::Label(l) => write!(f, "@{}({})", l.name, l.params.join(", ")),
+            Self::Pair(p, args) => {
+                write!(f, "pair '{} {}", p.0, p.1)?;
+                for arg in args {
+                    write!(f, " {arg}")?;
+                }
+                write!(f, "'")
+            }
             Self::Chord(_) => write!(f, "<chord>"),
             Self::Var(v) => write!(f, "{} = {}", v.name, v.value),
+            Self::Ret => write!(f, "ret"),
+            Self::Include(s) => write!(f, "include \"{s}\""),
+            Self::Import(s) => write!(f, "import {s}"),
+            Self::Dynamic(s) => write!(f, "{s}"),
+            Self::Choose(chords) => write!(f, "choose <{} chords>", chords.len()),
+            Self::Marker(s) => write!(f, "marker \"{s}\""),
+            Self::Hit(_) => write!(f, "<hit>"),
         }
     }
 }
@@ -33,6 +58,7 @@ pub trait ParsingFunctions {
     fn next(&mut self) -> Result<MusicalValues, ParseResponse>;
     fn skip_whitespace(&mut self);
     fn ident(&mut self) -> Result<String, ParseResponse>;
+    fn string(&mut self) -> Result<String, ParseResponse>;
     fn unsigned_int(&mut self) -> Result<usize, ParseResponse>;
     fn symbol(&mut self, c: char) -> bool;
     fn force_end(&mut self) -> Result<(), ParseResponse>;
@@ -44,7 +70,8 @@ impl<'a> From<&'a str> for Parser<'a> {
         let input = value;
         let cursor = 0;
         let lines = 0;
-        let size = input.len();
+        // the cursor counts characters, not bytes
+        let size = input.chars().count();
         Self {
             input,
             cursor,
@@ -110,6 +137,26 @@ impl<'a> ParsingFunctions for Parser<'a> {
         }
     }
 
+    fn string(&mut self) -> Result<String, ParseResponse> {
+        if !self.symbol('"') {
+            return Err(ParseResponse::NotPossible);
+        }
+        let mut out = "".to_string();
+        loop {
+            match self.input.chars().nth(self.cursor) {
+                Some('"') => {
+                    self.cursor += 1;
+                    break Ok(out);
+                }
+                Some('\n') | None => break Err(ParseResponse::String(self.lines)),
+                Some(c) => {
+                    out.push(c);
+                    self.cursor += 1;
+                }
+            }
+        }
+    }
+
     fn unsigned_int(&mut self) -> Result<usize, ParseResponse> {
         self.skip_whitespace();
         let mut out = "".to_string();
@@ -157,8 +204,8 @@ impl<'a> ParsingFunctions for Parser<'a> {
 
     fn force_end(&mut self) -> Result<(), ParseResponse> {
         self.skip_whitespace();
-        if let Some(c) = self.input.bytes().nth(self.cursor) {
-            if c != b'\n' && c != b';' {
+        if let Some(c) = self.input.chars().nth(self.cursor) {
+            if c != '\n' && c != ';' {
                 return Err(ParseResponse::EOL(self.lines));
             }
         }
@@ -168,18 +215,31 @@ impl<'a> ParsingFunctions for Parser<'a> {
 
     fn parse(&mut self) -> Result<(), ParseResponse> {
         while self.has_next() {
-            let next = self.next()?;
-            println!("{next:?}");
+            self.next()?;
         }
         Ok(())
     }
 
     fn next(&mut self) -> Result<MusicalValues, ParseResponse> {
         try_to_parse!(self.label(), MusicalValues::Label);
+        try_to_parse!(self.include(), MusicalValues::Include);
+        try_to_parse!(self.import(), MusicalValues::Import);
+        self.statement()
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Parses anything that may appear inside of a label's body.
+    fn statement(&mut self) -> Result<MusicalValues, ParseResponse> {
         try_to_parse!(self.chord(), MusicalValues::Chord);
+        try_to_parse!(self.hit(), MusicalValues::Hit);
+        try_to_parse!(self.command("ret"), |_| MusicalValues::Ret);
+        try_to_parse!(self.dynamic(), MusicalValues::Dynamic);
+        try_to_parse!(self.choose(), MusicalValues::Choose);
+        try_to_parse!(self.marker(), MusicalValues::Marker);
         let old = self.cursor;
         // try_to_parse!(self.pair(), MusicalValues::Pair);
-        match self.pair().map(MusicalValues::Pair) {
+        match self.pair().map(|(p, args)| MusicalValues::Pair(p, args)) {
             Ok(v) => return Ok(v),
             Err(ParseResponse::NotPossible) => self.cursor = old,
             Err(e) => return Err(e),
@@ -189,13 +249,140 @@ impl<'a> ParsingFunctions for Parser<'a> {
         if !self.has_next() {
             Err(ParseResponse::Done)
         } else {
-            println!("{:?}", self.input.chars().nth(self.cursor));
             Err(ParseResponse::Unexpected(self.lines))
         }
     }
-}
 
-impl<'a> Parser<'a> {
+    /// Consumes `word` if it's the next identifier, otherwise leaves the cursor untouched.
+    fn keyword(&mut self, word: &str) -> Result<(), ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        match self.ident() {
+            Ok(ident) if ident == word => Ok(()),
+            Ok(_) => {
+                self.cursor = old;
+                self.lines = lines;
+                Err(ParseResponse::NotPossible)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A keyword on its own, like `ret`, which is a variable instead when it's
+    /// followed by `:`.
+    fn command(&mut self, word: &str) -> Result<(), ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        self.keyword(word)?;
+        if self.symbol(':') {
+            self.cursor = old;
+            self.lines = lines;
+            return Err(ParseResponse::NotPossible);
+        }
+        Ok(())
+    }
+
+    fn include(&mut self) -> Result<String, ParseResponse> {
+        self.keyword("include")?;
+        match self.string() {
+            Err(ParseResponse::NotPossible) => Err(ParseResponse::String(self.lines)),
+            v => v,
+        }
+    }
+
+    fn import(&mut self) -> Result<String, ParseResponse> {
+        self.keyword("import")?;
+        match self.ident() {
+            Err(ParseResponse::NotPossible) => Err(ParseResponse::Ident(self.lines)),
+            v => v,
+        }
+    }
+
+    /// A dynamic marking on its own, `mf: 1` still being a variable.
+    fn dynamic(&mut self) -> Result<String, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        let marking = self.ident()?;
+        if dynamic_velocity(&marking).is_none() || self.symbol(':') {
+            self.cursor = old;
+            self.lines = lines;
+            return Err(ParseResponse::NotPossible);
+        }
+        Ok(marking)
+    }
+
+    /// `kick hat 1/8`, drum names followed by a duration.
+    fn hit(&mut self) -> Result<Hit, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        let mut drums = vec![];
+        loop {
+            let before = self.cursor;
+            let before_lines = self.lines;
+            match self.ident().ok().as_deref().and_then(Drum::from_name) {
+                Some(drum) => drums.push(drum),
+                None => {
+                    self.cursor = before;
+                    self.lines = before_lines;
+                    break;
+                }
+            }
+        }
+        if drums.is_empty() {
+            return Err(ParseResponse::NotPossible);
+        }
+        let duration = match self.duration() {
+            Err(ParseResponse::NotPossible) => {
+                // e.g. a variable called `kick`
+                self.cursor = old;
+                self.lines = lines;
+                return Err(ParseResponse::NotPossible);
+            }
+            v => v?,
+        };
+        Ok(Hit { drums, duration })
+    }
+
+    fn marker(&mut self) -> Result<String, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        self.keyword("marker")?;
+        match self.string() {
+            // `marker: ...` is a variable
+            Err(ParseResponse::NotPossible) => {
+                self.cursor = old;
+                self.lines = lines;
+                Err(ParseResponse::NotPossible)
+            }
+            v => v,
+        }
+    }
+
+    /// `choose { C E G 1 A C E 1 }`, the chords one of which gets played.
+    fn choose(&mut self) -> Result<Vec<Chord>, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        self.keyword("choose")?;
+        // `choose: ...` is a variable
+        if !self.symbol('{') {
+            self.cursor = old;
+            self.lines = lines;
+            return Err(ParseResponse::NotPossible);
+        }
+        let mut chords = vec![];
+        while !self.symbol('}') {
+            match self.chord() {
+                Ok(chord) => chords.push(chord),
+                Err(ParseResponse::NotPossible) => return Err(ParseResponse::Choose(self.lines)),
+                Err(e) => return Err(e),
+            }
+        }
+        if chords.is_empty() {
+            return Err(ParseResponse::Choose(self.lines));
+        }
+        Ok(chords)
+    }
+
     fn skip_comment(&mut self) {
         while self.has_next() {
             match self.input.chars().nth(self.cursor) {
@@ -231,10 +418,125 @@ impl<'a> Parser<'a> {
         if !self.symbol(':') {
             return Err(ParseResponse::NotPossible);
         }
-        let value = self.unsigned_int()? as f32;
+        if let Some(source) = self.source()? {
+            return Ok(Variable {
+                name,
+                value: Value::Source(source),
+            });
+        }
+        let value = match self.number() {
+            Ok(v) => Value::Num(v),
+            Err(ParseResponse::NotPossible) => match self.ident() {
+                // `key: F#`, where `#` would start a comment otherwise
+                Ok(mut v) if v.len() == 1 && self.peek(0) == Some('#') => {
+                    self.cursor += 1;
+                    v.push('#');
+                    Value::Ident(v)
+                }
+                Ok(v) => Value::Ident(v),
+                Err(ParseResponse::NotPossible) => {
+                    return Err(ParseResponse::Unexpected(self.lines))
+                }
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        };
         Ok(Variable { name, value })
     }
 
+    /// `sample "piano_c4.wav" root C4 loop` or `sf2 "GeneralUser.sf2" program 0`,
+    /// the options being optional and on the same line. The root is C4 unless it's
+    /// given, and the program and bank 0.
+    fn source(&mut self) -> Result<Option<Source>, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
+        let kind = match self.ident() {
+            Ok(kind) if kind == "sample" => kind,
+            // identifiers can't have digits
+            Ok(kind) if kind == "sf" && self.peek(0) == Some('2') => {
+                self.cursor += 1;
+                "sf2".to_string()
+            }
+            _ => {
+                self.cursor = old;
+                self.lines = lines;
+                return Ok(None);
+            }
+        };
+        let path = match self.string() {
+            Ok(path) => path,
+            // a variable set to `sample`
+            Err(ParseResponse::NotPossible) => {
+                self.cursor = old;
+                self.lines = lines;
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        if kind == "sf2" {
+            let mut program = 0;
+            let mut bank = 0;
+            while self.same_line() {
+                let option = if self.keyword("program").is_ok() {
+                    &mut program
+                } else if self.keyword("bank").is_ok() {
+                    &mut bank
+                } else {
+                    return Err(ParseResponse::Unexpected(self.lines));
+                };
+                *option = match self.unsigned_int() {
+                    Ok(v) => v as u16,
+                    Err(ParseResponse::NotPossible) => return Err(ParseResponse::Int(self.lines)),
+                    Err(e) => return Err(e),
+                };
+            }
+            return Ok(Some(Source::SoundFont {
+                path,
+                program,
+                bank,
+            }));
+        }
+        let mut root = 60;
+        let mut looped = false;
+        while self.same_line() {
+            if self.keyword("loop").is_ok() {
+                looped = true;
+            } else if self.keyword("root").is_ok() {
+                root = self.key()?;
+            } else {
+                return Err(ParseResponse::Unexpected(self.lines));
+            }
+        }
+        Ok(Some(Source::Sample { path, root, looped }))
+    }
+
+    /// A note with its octave, like `C#4`, as a MIDI key.
+    fn key(&mut self) -> Result<i32, ParseResponse> {
+        let Ok(mut name) = self.ident() else {
+            return Err(ParseResponse::Note(self.lines));
+        };
+        if self.peek(0) == Some('#') {
+            self.cursor += 1;
+            name.push('#');
+        }
+        let semitone = note_semitone(&name).ok_or(ParseResponse::Note(self.lines))?;
+        let octave = match self.unsigned_int() {
+            Ok(octave) => octave as i32,
+            Err(ParseResponse::NotPossible) => return Err(ParseResponse::Note(self.lines)),
+            Err(e) => return Err(e),
+        };
+        Ok(semitone + (octave + 1) * 12)
+    }
+
+    /// Whether there's more to parse before the end of the line.
+    fn same_line(&self) -> bool {
+        let mut offset = 0;
+        while let Some(' ' | '\t' | '\r') = self.peek(offset) {
+            offset += 1;
+        }
+        !matches!(self.peek(offset), None | Some('\n' | ';' | '#'))
+    }
+
     fn duration(&mut self) -> Result<f32, ParseResponse> {
         let numerator = self.unsigned_int()? as f32;
         let denominator = if self.symbol('/') {
@@ -245,26 +547,175 @@ impl<'a> Parser<'a> {
         Ok(numerator / denominator)
     }
 
-    fn pair(&mut self) -> Result<(String, String), ParseResponse> {
+    /// A duration that may be negative, like `-3` or `1/16`.
+    fn number(&mut self) -> Result<f32, ParseResponse> {
+        self.skip_whitespace();
+        if self.peek(0) == Some('-') && self.peek(1).is_some_and(|c| c.is_numeric()) {
+            self.cursor += 1;
+            Ok(-self.duration()?)
+        } else {
+            self.duration()
+        }
+    }
+
+    fn pair(&mut self) -> Result<((String, String), Vec<f32>), ParseResponse> {
         let fst = self.ident()?;
         let snd = self.ident()?;
-        Ok((fst, snd))
+        let args = self.arguments()?;
+        Ok(((fst, snd), args))
     }
 
-    fn label(&mut self) -> Result<String, ParseResponse> {
-        if self.symbol('@') {
-            let ident = self.ident();
-            match ident {
-                Err(ParseResponse::NotPossible) => Err(ParseResponse::Ident(self.lines)),
-                Ok(v) => Ok(v),
-                _ => unreachable!(),
+    /// Numbers following a pair on the same line, e.g. `goto phrase 5 1/2`.
+    fn arguments(&mut self) -> Result<Vec<f32>, ParseResponse> {
+        let mut args = vec![];
+        loop {
+            while let Some(' ' | '\t') = self.input.chars().nth(self.cursor) {
+                self.cursor += 1;
+            }
+            match (self.peek(0), self.peek(1)) {
+                (Some(c), _) if c.is_numeric() => args.push(self.number()?),
+                (Some('-'), Some(c)) if c.is_numeric() => args.push(self.number()?),
+                _ => break Ok(args),
             }
+        }
+    }
+
+    /// `(a, b, ...)` after a label's name.
+    fn parameters(&mut self) -> Result<Vec<String>, ParseResponse> {
+        let mut params = vec![];
+        if !self.symbol('(') || self.symbol(')') {
+            return Ok(params);
+        }
+        loop {
+            match self.ident() {
+                Ok(v) => params.push(v),
+                Err(ParseResponse::NotPossible) => return Err(ParseResponse::Ident(self.lines)),
+                Err(e) => return Err(e),
+            }
+            if self.symbol(')') {
+                break Ok(params);
+            }
+            if !self.symbol(',') {
+                break Err(ParseResponse::Unexpected(self.lines));
+            }
+        }
+    }
+
+    fn label(&mut self) -> Result<Label, ParseResponse> {
+        if !self.symbol('@') {
+            return Err(ParseResponse::NotPossible);
+        }
+        let name = match self.ident() {
+            Err(ParseResponse::NotPossible) => return Err(ParseResponse::Ident(self.lines)),
+            Ok(v) => v,
+            _ => unreachable!(),
+        };
+        let params = self.parameters()?;
+        let body = if self.symbol('{') {
+            self.block()?
         } else {
-            Err(ParseResponse::NotPossible)
+            self.body()?
+        };
+        Ok(Label { name, params, body })
+    }
+
+    /// `@name { ... }`: the body ends at the matching `}`.
+    fn block(&mut self) -> Result<Vec<MusicalValues>, ParseResponse> {
+        let mut body = vec![];
+        loop {
+            if self.symbol('}') {
+                break Ok(body);
+            }
+            if self.symbol('@') {
+                break Err(ParseResponse::NestedLabel(self.lines));
+            }
+            match self.statement() {
+                Ok(v) => body.push(v),
+                Err(ParseResponse::Done) => break Err(ParseResponse::Block(self.lines)),
+                Err(e) => break Err(e),
+            }
+        }
+    }
+
+    /// `@name ...`: the body ends at `end`, or for older files at the next label
+    /// or the end of the file.
+    fn body(&mut self) -> Result<Vec<MusicalValues>, ParseResponse> {
+        let mut body = vec![];
+        loop {
+            self.skip_whitespace();
+            if !self.has_next() || self.input.chars().nth(self.cursor) == Some('@') {
+                break Ok(body);
+            }
+            match self.command("end") {
+                Ok(()) => break Ok(body),
+                Err(ParseResponse::NotPossible) => (),
+                Err(e) => break Err(e),
+            }
+            match self.statement() {
+                Ok(v) => body.push(v),
+                Err(ParseResponse::Done) => break Ok(body),
+                Err(e) => break Err(e),
+            }
         }
     }
 
-    fn get_note(&mut self) -> Result<Note, ParseResponse> {
+    /// A note, or all notes of a chord symbol.
+    fn get_notes(&mut self) -> Result<Vec<Note>, ParseResponse> {
+        let mut notes = if self.symbol('^') {
+            let degree = match self.unsigned_int() {
+                Ok(v) => Degree::Num(v as i32),
+                Err(ParseResponse::NotPossible) => match self.ident() {
+                    Ok(v) => Degree::Var(v),
+                    Err(_) => return Err(ParseResponse::Int(self.lines)),
+                },
+                Err(e) => return Err(e),
+            };
+            vec![Note::new(Pitch::Degree(degree))]
+        } else if self.symbol('+') || self.symbol('-') {
+            let sign = if self.input.chars().nth(self.cursor - 1) == Some('-') {
+                -1
+            } else {
+                1
+            };
+            let interval = match self.unsigned_int() {
+                Ok(v) => v as i32 * sign,
+                Err(ParseResponse::NotPossible) => return Err(ParseResponse::Int(self.lines)),
+                Err(e) => return Err(e),
+            };
+            vec![Note::new(Pitch::Interval(interval))]
+        } else {
+            self.named_notes()?
+        };
+        let mut articulations = vec![];
+        while let Some(articulation) = self
+            .input
+            .chars()
+            .nth(self.cursor)
+            .and_then(Articulation::from_char)
+        {
+            self.cursor += 1;
+            articulations.push(articulation);
+        }
+        let velocity = if let Some('!') = self.input.chars().nth(self.cursor) {
+            self.cursor += 1;
+            match self.unsigned_int() {
+                Ok(v) => Some(v.clamp(1, 127) as u8),
+                Err(ParseResponse::NotPossible) => return Err(ParseResponse::Int(self.lines)),
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        self.skip_whitespace();
+        for note in notes.iter_mut() {
+            note.articulations = articulations.clone();
+            note.velocity = velocity;
+        }
+        Ok(notes)
+    }
+
+    /// A note written by its name, or all notes of a chord symbol.
+    fn named_notes(&mut self) -> Result<Vec<Note>, ParseResponse> {
         let note = if self.symbol('A') {
             NoteName::A
         } else if self.symbol('B') || self.symbol('H') {
@@ -293,38 +744,154 @@ impl<'a> Parser<'a> {
                     self.cursor += 1;
                     NoteModifier::Flat
                 }
-                _ => {
-                    self.skip_whitespace();
-                    NoteModifier::None
-                }
+                _ => NoteModifier::None,
             },
             None => return Err(ParseResponse::NotPossible),
         };
-        Ok(Note { note, modifier })
+        let root = Note::new(Pitch::Named(note, modifier));
+        match self.chord_symbol(&root) {
+            Some(notes) => Ok(notes),
+            None => Ok(vec![root]),
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.input.chars().nth(self.cursor + offset)
+    }
+
+    /// The notes of a chord symbol such as `Am`, `Cmaj7`, `G7/B` or `C/1` (the first
+    /// inversion of C major) following `root`, leaves the cursor untouched if there's none.
+    fn chord_symbol(&mut self, root: &Note) -> Option<Vec<Note>> {
+        let rest: String = self.input.chars().skip(self.cursor).take(4).collect();
+        let quality = CHORD_QUALITIES
+            .iter()
+            .find(|(suffix, _)| rest.starts_with(suffix));
+        let old = self.cursor;
+        if let Some((suffix, _)) = quality {
+            self.cursor += suffix.len();
+        }
+        let is_slash = self.peek(0) == Some('/')
+            && self
+                .peek(1)
+                .is_some_and(|c| c.is_ascii_digit() || NoteName::from_char(c).is_some());
+        // `G7/8` is G played for 7/8 rather than an inversion of G7, which is
+        // followed by its duration
+        let fraction = quality
+            .is_some_and(|(suffix, _)| suffix.bytes().all(|c| c.is_ascii_digit()))
+            && self.peek(1).is_some_and(|c| c.is_ascii_digit())
+            && {
+                self.cursor += 2;
+                let inversion = self.duration_follows();
+                self.cursor -= 2;
+                !inversion
+            };
+        if is_slash && fraction {
+            self.cursor = old;
+            return None;
+        }
+        let intervals = match quality {
+            Some((suffix, intervals)) => {
+                // `G7` followed by anything but a duration is G played for 7
+                let digit = suffix.starts_with(|c: char| c.is_ascii_digit());
+                if digit && !is_slash && !self.duration_follows() {
+                    self.cursor = old;
+                    return None;
+                }
+                *intervals
+            }
+            // a major triad
+            None if is_slash => &[0, 4, 7],
+            None => return None,
+        };
+        let mut notes: Vec<Note> = intervals
+            .iter()
+            .map(|interval| Note {
+                shift: *interval,
+                ..root.clone()
+            })
+            .collect();
+        if !is_slash {
+            return Some(notes);
+        }
+        self.cursor += 1;
+        match self.peek(0).and_then(NoteName::from_char) {
+            Some(name) => {
+                self.cursor += 1;
+                let modifier = match self.peek(0) {
+                    Some('#') => NoteModifier::Sharp,
+                    Some('b') => NoteModifier::Flat,
+                    _ => NoteModifier::None,
+                };
+                if !matches!(modifier, NoteModifier::None) {
+                    self.cursor += 1;
+                }
+                let mut bass = Note::new(Pitch::Named(name, modifier));
+                // the bass is the lowest note
+                if bass.semitone() >= root.semitone() {
+                    bass.shift = -12;
+                }
+                notes.insert(0, bass);
+            }
+            None => {
+                let inversion = self.peek(0).and_then(|c| c.to_digit(10)).unwrap_or(0);
+                self.cursor += 1;
+                for note in notes.iter_mut().take(inversion as usize) {
+                    note.shift += 12;
+                }
+            }
+        }
+        Some(notes)
+    }
+
+    /// Whether a duration comes next on the same line, after some whitespace.
+    fn duration_follows(&self) -> bool {
+        let mut offset = 0;
+        while let Some(' ' | '\t') = self.peek(offset) {
+            offset += 1;
+        }
+        offset > 0 && self.peek(offset).is_some_and(|c| c.is_ascii_digit())
     }
 
     fn chord(&mut self) -> Result<Chord, ParseResponse> {
+        let old = self.cursor;
+        let lines = self.lines;
         let mut notes: Vec<Note> = vec![];
         loop {
-            let note = self.get_note();
+            let note = self.get_notes();
             if note.is_err() {
                 break;
             }
-            notes.push(note.unwrap());
+            notes.extend(note.unwrap());
         }
         if notes.is_empty() {
             return Err(ParseResponse::NotPossible);
         }
-        let duration = self.duration()?;
-        Ok(Chord { notes, duration })
+        let duration = match self.duration() {
+            Err(ParseResponse::NotPossible) => {
+                // e.g. a variable called `Amp`
+                self.cursor = old;
+                self.lines = lines;
+                return Err(ParseResponse::NotPossible);
+            }
+            v => v?,
+        };
+        let lyric = match self.string() {
+            Err(ParseResponse::NotPossible) => None,
+            v => Some(v?),
+        };
+        Ok(Chord {
+            notes,
+            duration,
+            lyric,
+        })
     }
 
     pub fn get_err_line(&self) -> String {
         let start = {
             let mut cursor = self.cursor;
             while cursor > 0 {
-                if let Some(c) = self.input.bytes().nth(cursor) {
-                    if c == b'\n' {
+                if let Some(c) = self.input.chars().nth(cursor) {
+                    if c == '\n' {
                         break;
                     }
                 } else {
@@ -337,8 +904,8 @@ impl<'a> Parser<'a> {
         let end = {
             let mut cursor = self.cursor;
             while self.has_next() {
-                if let Some(c) = self.input.bytes().nth(cursor) {
-                    if c == b'\n' {
+                if let Some(c) = self.input.chars().nth(cursor) {
+                    if c == '\n' {
                         break;
                     }
                 } else {
@@ -358,3 +925,76 @@ impl<'a> Parser<'a> {
         lines
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(input: &str) -> Chord {
+        match Parser::from(input).next() {
+            Ok(MusicalValues::Chord(chord)) => chord,
+            v => panic!("'{input}' isn't a chord: {v:?}"),
+        }
+    }
+
+    fn shifts(chord: &Chord) -> Vec<i32> {
+        chord.notes.iter().map(|n| n.shift).collect()
+    }
+
+    #[test]
+    fn fraction_after_digit_quality_is_a_duration() {
+        for (input, duration) in [
+            ("G7/8", 7.0 / 8.0),
+            ("A6/8", 6.0 / 8.0),
+            ("C9/16", 9.0 / 16.0),
+        ] {
+            let chord = chord(input);
+            assert!(chord.is_note(), "'{input}' should be a single note");
+            assert_eq!(chord.duration, duration);
+        }
+    }
+
+    #[test]
+    fn slash_chords() {
+        assert_eq!(shifts(&chord("C/1 1/4")), [12, 4, 7]);
+        assert_eq!(shifts(&chord("C7/1 1/4")), [12, 4, 7, 10]);
+        assert_eq!(shifts(&chord("G9/2 1/4")), [12, 16, 7, 10, 14]);
+        assert_eq!(shifts(&chord("G7/B 1/4")), [-12, 0, 4, 7, 10]);
+        assert_eq!(shifts(&chord("G7 1/4")), [0, 4, 7, 10]);
+    }
+
+    #[test]
+    fn ret_and_end_can_be_variables() {
+        let mut parser = Parser::from("@main\nret: 3\nend: 1\nC 1/4\nend\n");
+        let Ok(MusicalValues::Label(label)) = parser.next() else {
+            panic!("expected a label");
+        };
+        let names: Vec<_> = (label.body.iter())
+            .map(|v| match v {
+                MusicalValues::Var(v) => v.name.as_str(),
+                MusicalValues::Chord(_) => "chord",
+                v => panic!("unexpected {v}"),
+            })
+            .collect();
+        assert_eq!(names, ["ret", "end", "chord"]);
+    }
+
+    #[test]
+    fn non_ascii_lyrics() {
+        let mut parser = Parser::from("C 1/4 \"café\"\nE 1/4 \"naïve\"\nG 1/4 &\n");
+        for lyric in ["café", "naïve"] {
+            match parser.next() {
+                Ok(MusicalValues::Chord(chord)) => assert_eq!(chord.lyric.as_deref(), Some(lyric)),
+                v => panic!("expected a chord with the lyric '{lyric}': {v:?}"),
+            }
+        }
+        // the line after them is where it should be
+        assert!(matches!(parser.next(), Ok(MusicalValues::Chord(_))));
+        assert!(parser.next().is_err());
+        assert_eq!(parser.get_err_line(), "\nG 1/4 &");
+
+        let mut parser = Parser::from("C 1/4 \"café\"");
+        assert!(matches!(parser.next(), Ok(MusicalValues::Chord(_))));
+        assert!(!parser.has_next());
+    }
+}
@@ -0,0 +1,32 @@
+/// xorshift, a small generator whose numbers only depend on its seed so that
+/// renders can be reproduced.
+#[derive(Debug, Clone)]
+pub struct Random(u32);
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // spreads out the bits of small seeds, which would otherwise start off
+        // with similar numbers. xorshift gets stuck on 0
+        Self(seed.wrapping_add(1).wrapping_mul(0x9e37_79b9).max(1))
+    }
+
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// A number from 0 up to, but not including, 1.
+    pub fn float(&mut self) -> f32 {
+        (self.next() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// A whole number from `min` to `max`, both included.
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next() as i64).rem_euclid(max - min + 1)
+    }
+}
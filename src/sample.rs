@@ -0,0 +1,283 @@
+use std::{
+    f64::consts::PI,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::OnceLock,
+};
+
+/// Sound read from a file, mixed down to mono.
+#[derive(Debug)]
+pub struct Sample {
+    /// Samples per second it was recorded at.
+    pub rate: u32,
+    pub data: Vec<f32>,
+    mipmaps: Mipmaps,
+}
+
+impl Sample {
+    /// Reads a WAV file of 8, 16, 24 or 32 bit PCM, or 32 or 64 bit floats.
+    pub fn read_wav(path: &Path) -> io::Result<Self> {
+        Self::parse_wav(&fs::read(path)?)
+    }
+
+    /// The sound in the bytes of a WAV file.
+    fn parse_wav(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a WAV file"));
+        }
+        // (format, channels, sample rate, bits per sample)
+        let mut format = None;
+        let mut data = None;
+        for (id, body) in riff_chunks(&bytes[12..]) {
+            match &id {
+                b"fmt " if body.len() >= 16 => {
+                    let mut tag = u16::from_le_bytes([body[0], body[1]]);
+                    // WAVE_FORMAT_EXTENSIBLE keeps the actual format in its sub format
+                    if tag == 0xFFFE && body.len() >= 26 {
+                        tag = u16::from_le_bytes([body[24], body[25]]);
+                    }
+                    let channels = u16::from_le_bytes([body[2], body[3]]);
+                    let rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    format = Some((tag, channels, rate, bits));
+                }
+                b"data" => data = Some(body),
+                _ => (),
+            }
+        }
+        let (Some((tag, channels, rate, bits)), Some(data)) = (format, data) else {
+            return Err(invalid("missing the fmt or data chunk"));
+        };
+        if channels == 0 {
+            return Err(invalid("no channels"));
+        }
+        if rate == 0 {
+            return Err(invalid("a sample rate of 0"));
+        }
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+            (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (1, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+            (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            (3, 64) => |b| f64::from_le_bytes(b[0..8].try_into().unwrap()) as f32,
+            _ => return Err(invalid("unsupported sample format")),
+        };
+        let width = bits as usize / 8;
+        let frame = width * channels as usize;
+        let data: Vec<f32> = data
+            .chunks_exact(frame)
+            .map(|f| f.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+            .collect();
+        if data.is_empty() {
+            return Err(invalid("no samples"));
+        }
+        let mipmaps = Mipmaps::new(&data);
+        Ok(Self {
+            rate,
+            data,
+            mipmaps,
+        })
+    }
+
+    /// The value `position` samples into the sound, see `Mipmaps::at`.
+    pub fn at(&self, position: f64, step: f64) -> f32 {
+        self.mipmaps.at(&self.data, position, step)
+    }
+}
+
+/// Copies of a sound at a half, a quarter and so on of its rate, which high notes
+/// are read from so that they don't sum more and more of it to be band-limited.
+#[derive(Debug)]
+pub struct Mipmaps(Vec<Vec<f32>>);
+
+impl Mipmaps {
+    pub fn new(data: &[f32]) -> Self {
+        let mut levels: Vec<Vec<f32>> = vec![];
+        loop {
+            let above = levels.last().map_or(data, Vec::as_slice);
+            if above.len() < 2 {
+                return Self(levels);
+            }
+            let half = (0..above.len().div_ceil(2))
+                .map(|i| resample(above, 2.0 * i as f64, 2.0))
+                .collect();
+            levels.push(half);
+        }
+    }
+
+    /// `resample` of `data`, which the copies were made of, read from the first copy
+    /// where `step` is at most `MAX_STEP`.
+    pub fn at(&self, data: &[f32], mut position: f64, mut step: f64) -> f32 {
+        let mut data = data;
+        for level in &self.0 {
+            if step <= MAX_STEP {
+                break;
+            }
+            position /= 2.0;
+            step /= 2.0;
+            data = level;
+        }
+        resample(data, position, step)
+    }
+}
+
+/// Zero crossings of the sinc on each side of a position read by `resample`.
+const ZEROS: f64 = 8.0;
+
+/// The largest `step` that `resample` band-limits for.
+const MAX_STEP: f64 = 4.0;
+
+/// Points of `kernel` in between two zero crossings.
+const RESOLUTION: usize = 512;
+
+/// The value `position` samples into `data`, in between samples if it isn't a whole
+/// number, where `data` is read `step` samples at a time. It's band-limited by a
+/// windowed sinc, which cuts off what would alias when `step` is over 1.
+pub fn resample(data: &[f32], position: f64, step: f64) -> f32 {
+    // the cutoff relative to the Nyquist frequency of `data`, which is limited so
+    // that very high notes don't take forever, `Mipmaps` reading those instead
+    let cutoff = 1.0 / step.clamp(1.0, MAX_STEP);
+    let width = ZEROS / cutoff;
+    let first = (position - width).ceil().max(0.0) as usize;
+    let last = ((position + width).floor().max(0.0) as usize).min(data.len().saturating_sub(1));
+    let kernel = kernel();
+    let mut sum = 0.0;
+    for (i, value) in data.iter().enumerate().take(last + 1).skip(first) {
+        // the kernel is even, so only its right half is kept
+        let x = (i as f64 - position).abs() * cutoff * RESOLUTION as f64;
+        let (index, fraction) = (x as usize, x.fract());
+        let point = |i: usize| kernel.get(i).copied().unwrap_or(0.0);
+        let (a, b) = (point(index), point(index + 1));
+        sum += *value as f64 * cutoff * (a + (b - a) * fraction);
+    }
+    sum as f32
+}
+
+/// The sinc with a Hann window over `ZEROS` zero crossings, from 0 on, worked out
+/// once as `resample` reads it for every sample of every note.
+fn kernel() -> &'static [f64] {
+    static KERNEL: OnceLock<Vec<f64>> = OnceLock::new();
+    KERNEL.get_or_init(|| {
+        (0..=ZEROS as usize * RESOLUTION)
+            .map(|i| {
+                let x = i as f64 / RESOLUTION as f64;
+                sinc(x) * (0.5 + 0.5 * (PI * x / ZEROS).cos())
+            })
+            .collect()
+    })
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The ids and bodies of the chunks in a RIFF list.
+pub fn riff_chunks(mut bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 8 {
+        let id = bytes[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        chunks.push((id, &bytes[8..bytes.len().min(8 + size)]));
+        // chunks are padded to an even size
+        bytes = &bytes[(8 + size + size % 2).min(bytes.len())..];
+    }
+    chunks
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A WAV file of `channels` channels of `bits` bit samples of the format `tag`,
+    /// which the extensible format keeps in its sub format if `extensible`.
+    fn wav(
+        tag: u16,
+        bits: u16,
+        channels: u16,
+        extensible: bool,
+        rate: u32,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let align = channels * bits / 8;
+        let mut fmt = [
+            &(if extensible { 0xFFFE } else { tag }).to_le_bytes()[..],
+            &channels.to_le_bytes(),
+            &rate.to_le_bytes(),
+            &(rate * align as u32).to_le_bytes(),
+            &align.to_le_bytes(),
+            &bits.to_le_bytes(),
+        ]
+        .concat();
+        if extensible {
+            fmt.extend(22u16.to_le_bytes());
+            fmt.extend(bits.to_le_bytes());
+            fmt.extend([0; 4]);
+            fmt.extend(tag.to_le_bytes());
+            fmt.extend([0; 14]);
+        }
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, body) in [(b"fmt ", &fmt[..]), (b"data", data)] {
+            bytes.extend(id);
+            bytes.extend((body.len() as u32).to_le_bytes());
+            bytes.extend(body);
+        }
+        bytes
+    }
+
+    /// The sound of a mono WAV file at 8 kHz.
+    fn read(tag: u16, bits: u16, extensible: bool, data: &[u8]) -> Vec<f32> {
+        let sample = Sample::parse_wav(&wav(tag, bits, 1, extensible, 8000, data)).unwrap();
+        assert_eq!(sample.rate, 8000);
+        sample.data
+    }
+
+    fn i16s(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn f32s(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn pcm_and_float_samples() {
+        let expected = [0.0, 0.5, -1.0];
+        assert_eq!(read(1, 8, false, &[128, 192, 0]), expected);
+        assert_eq!(read(1, 16, false, &i16s(&[0, 16384, -32768])), expected);
+        let pcm24 = [0, 0, 0, 0, 0, 0x40, 0, 0, 0x80];
+        assert_eq!(read(1, 24, false, &pcm24), expected);
+        assert_eq!(read(3, 32, false, &f32s(&expected)), expected);
+    }
+
+    #[test]
+    fn extensible_format() {
+        assert_eq!(read(1, 16, true, &i16s(&[16384])), [0.5]);
+        assert_eq!(read(3, 32, true, &f32s(&[0.25])), [0.25]);
+    }
+
+    #[test]
+    fn stereo_is_mixed_down_to_mono() {
+        let bytes = wav(1, 16, 2, false, 8000, &i16s(&[16384, 0, -32768, 16384]));
+        assert_eq!(Sample::parse_wav(&bytes).unwrap().data, [0.25, -0.25]);
+    }
+
+    #[test]
+    fn rejects_a_rate_of_zero_and_no_samples() {
+        let error = |bytes: Vec<u8>| Sample::parse_wav(&bytes).unwrap_err().to_string();
+        assert_eq!(
+            error(wav(1, 16, 1, false, 0, &[0, 0])),
+            "a sample rate of 0"
+        );
+        assert_eq!(error(wav(1, 16, 1, false, 8000, &[])), "no samples");
+    }
+}
@@ -0,0 +1,466 @@
+use std::{collections::HashMap, fs, io, path::Path, sync::OnceLock};
+
+use crate::sample::{invalid, riff_chunks, Mipmaps};
+use crate::synth::Envelope;
+
+// generators used by the player, see the SoundFont 2.01 specification.
+const START_OFFSET: u16 = 0;
+const END_OFFSET: u16 = 1;
+const LOOP_START_OFFSET: u16 = 2;
+const LOOP_END_OFFSET: u16 = 3;
+const START_COARSE_OFFSET: u16 = 4;
+const END_COARSE_OFFSET: u16 = 12;
+const ATTACK: u16 = 34;
+const DECAY: u16 = 36;
+const SUSTAIN: u16 = 37;
+const RELEASE: u16 = 38;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const VELOCITY_RANGE: u16 = 44;
+const LOOP_START_COARSE_OFFSET: u16 = 45;
+const ATTENUATION: u16 = 48;
+const LOOP_END_COARSE_OFFSET: u16 = 50;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const ROOT_KEY: u16 = 58;
+
+/// Timecents of 1 millisecond, the default of envelope times.
+const INSTANT: i16 = -12000;
+
+/// Generator amounts by their operator.
+type Generators = HashMap<u16, [u8; 2]>;
+
+/// Sounds read from an SF2 file.
+#[derive(Debug)]
+pub struct SoundFont {
+    /// Every sample of the file, one after another.
+    pub data: Vec<f32>,
+    pub presets: Vec<Preset>,
+}
+
+#[derive(Debug)]
+pub struct Preset {
+    pub bank: u16,
+    pub program: u16,
+    pub zones: Vec<Zone>,
+}
+
+/// A sample played for a range of keys and velocities, its generators being
+/// those of the instrument zone plus those of the preset zone.
+#[derive(Debug)]
+pub struct Zone {
+    pub keys: (u8, u8),
+    pub velocities: (u8, u8),
+    /// Where the sample and its loop are in `SoundFont::data`.
+    start: usize,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    looped: bool,
+    rate: u32,
+    /// Key the sample plays at unchanged, tuning included.
+    root: f32,
+    gain: f32,
+    envelope: Envelope,
+    /// Of the sample from `start` to `end`, made the first time it's played as a
+    /// font has many more samples than a program uses.
+    mipmaps: OnceLock<Mipmaps>,
+}
+
+impl SoundFont {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// The SoundFont in the bytes of an SF2 file.
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+            return Err(invalid("not a SoundFont"));
+        }
+        let mut chunks = HashMap::new();
+        for (id, body) in riff_chunks(&bytes[12..]) {
+            if &id == b"LIST" && body.len() >= 4 {
+                chunks.extend(riff_chunks(&body[4..]));
+            }
+        }
+        let chunk = |id: &[u8; 4]| {
+            chunks.get(id).copied().ok_or_else(|| {
+                invalid(&format!(
+                    "missing the {} chunk",
+                    String::from_utf8_lossy(id)
+                ))
+            })
+        };
+        let data = chunk(b"smpl")?
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect::<Vec<_>>();
+        let phdr = records(chunk(b"phdr")?, 38);
+        let pbag = records(chunk(b"pbag")?, 4);
+        let pgen = records(chunk(b"pgen")?, 4);
+        let inst = records(chunk(b"inst")?, 22);
+        let ibag = records(chunk(b"ibag")?, 4);
+        let igen = records(chunk(b"igen")?, 4);
+        let shdr = records(chunk(b"shdr")?, 46);
+
+        let mut presets = Vec::new();
+        // the last header of presets and instruments only marks the end of the list
+        for header in phdr.windows(2) {
+            let (from, to) = (word(header[0], 24) as usize, word(header[1], 24) as usize);
+            let mut zones = Vec::new();
+            for preset_zone in bag_zones(&pbag, &pgen, from, to, INSTRUMENT) {
+                let instrument = amount(&preset_zone, INSTRUMENT).unwrap_or(0) as u16 as usize;
+                let (Some(first), Some(last)) = (inst.get(instrument), inst.get(instrument + 1))
+                else {
+                    continue;
+                };
+                let (from, to) = (word(first, 20) as usize, word(last, 20) as usize);
+                for zone in bag_zones(&ibag, &igen, from, to, SAMPLE_ID) {
+                    let id = amount(&zone, SAMPLE_ID).unwrap_or(0) as u16 as usize;
+                    let Some(header) = shdr.get(id) else {
+                        continue;
+                    };
+                    let kind = word(header, 44);
+                    // right channels of stereo samples are left out as everything is
+                    // mixed into one channel, and ROM samples aren't in the file
+                    if kind & 2 != 0 || kind & 0x8000 != 0 {
+                        continue;
+                    }
+                    if let Some(zone) = Zone::new(&zone, &preset_zone, header, data.len()) {
+                        zones.push(zone);
+                    }
+                }
+            }
+            presets.push(Preset {
+                bank: word(header[0], 22),
+                program: word(header[0], 20),
+                zones,
+            });
+        }
+        Ok(Self { data, presets })
+    }
+
+    /// Index of the preset with the given bank and program.
+    pub fn preset(&self, bank: u16, program: u16) -> Option<usize> {
+        self.presets
+            .iter()
+            .position(|p| p.bank == bank && p.program == program)
+    }
+}
+
+impl Zone {
+    /// `header` is the sample's header and `samples` how many samples the file has.
+    fn new(zone: &Generators, preset: &Generators, header: &[u8], samples: usize) -> Option<Self> {
+        let keys = intersect(range(zone, KEY_RANGE), range(preset, KEY_RANGE))?;
+        let velocities = intersect(range(zone, VELOCITY_RANGE), range(preset, VELOCITY_RANGE))?;
+        // preset generators are added to the instrument's
+        let sum = |op: u16, default: i16| {
+            amount(zone, op).unwrap_or(default) as i32 + amount(preset, op).unwrap_or(0) as i32
+        };
+        let offset = |fine: u16, coarse: u16| {
+            amount(zone, fine).unwrap_or(0) as i64
+                + amount(zone, coarse).unwrap_or(0) as i64 * 32768
+        };
+        let address = |position: usize, offset: i64| {
+            (position as i64 + offset).clamp(0, samples as i64) as usize
+        };
+        let start = address(dword(header, 20), offset(START_OFFSET, START_COARSE_OFFSET));
+        let end = address(dword(header, 24), offset(END_OFFSET, END_COARSE_OFFSET));
+        let loop_start = address(
+            dword(header, 28),
+            offset(LOOP_START_OFFSET, LOOP_START_COARSE_OFFSET),
+        );
+        let loop_end = address(
+            dword(header, 32),
+            offset(LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET),
+        );
+        let rate = dword(header, 36) as u32;
+        if start >= end || rate == 0 {
+            return None;
+        }
+
+        let root = match amount(zone, ROOT_KEY) {
+            Some(key) if key >= 0 => key as i32,
+            _ => match header[40] {
+                key @ 0..=127 => key as i32,
+                _ => 60,
+            },
+        };
+        // cents
+        let tune = sum(COARSE_TUNE, 0) * 100 + sum(FINE_TUNE, 0) + header[41] as i8 as i32;
+        // 1 and 3 loop, the latter only until the note is released which is close enough
+        let looped = amount(zone, SAMPLE_MODES).unwrap_or(0) & 1 == 1 && loop_start < loop_end;
+
+        // attenuations are in centibels
+        let decibels = |centibels: i32| 10f32.powf(-centibels.clamp(0, 1440) as f32 / 200.0);
+        let seconds = |timecents: i32| 2f32.powf(timecents as f32 / 1200.0);
+        let sustain = sum(SUSTAIN, 0).clamp(0, 1440);
+        let envelope = Envelope {
+            attack: seconds(sum(ATTACK, INSTANT)),
+            // the decay is how long it takes to fall by 96 dB, the sustain is usually
+            // reached sooner
+            decay: seconds(sum(DECAY, INSTANT)) * (sustain as f32 / 960.0).min(1.0),
+            sustain: decibels(sustain),
+            release: seconds(sum(RELEASE, INSTANT)),
+        };
+        Some(Self {
+            keys,
+            velocities,
+            start,
+            end,
+            loop_start,
+            loop_end,
+            looped,
+            rate,
+            root: root as f32 - tune as f32 / 100.0,
+            gain: decibels(sum(ATTENUATION, 0)),
+            envelope,
+            mipmaps: OnceLock::new(),
+        })
+    }
+
+    pub fn plays(&self, key: i32, velocity: u8) -> bool {
+        let key = key.clamp(0, 127) as u8;
+        (self.keys.0..=self.keys.1).contains(&key)
+            && (self.velocities.0..=self.velocities.1).contains(&velocity)
+    }
+
+    /// The sample played at `key` and held for `held` seconds, in samples of `font`,
+    /// at `rate` samples per second.
+    pub fn sound(&self, font: &SoundFont, key: i32, held: f32, rate: f32) -> Vec<f32> {
+        let step =
+            2f64.powf((key as f64 - self.root as f64) / 12.0) * self.rate as f64 / rate as f64;
+        let samples = ((held + self.envelope.release) * rate) as usize;
+        let mut out = Vec::with_capacity(samples);
+        let length = (self.end - self.start) as f64;
+        let (loop_start, loop_end) = (
+            self.loop_start.saturating_sub(self.start) as f64,
+            self.loop_end.saturating_sub(self.start) as f64,
+        );
+        // the zone's sample only, so that its neighbours aren't heard
+        let data = &font.data[self.start..self.end];
+        let mipmaps = self.mipmaps.get_or_init(|| Mipmaps::new(data));
+        let mut position = 0.0;
+        for i in 0..samples {
+            if self.looped && position >= loop_end {
+                position -= loop_end - loop_start;
+            }
+            if position >= length {
+                break;
+            }
+            let level = self.envelope.level(i as f32 / rate, held);
+            out.push(self.gain * level * mipmaps.at(data, position, step));
+            position += step;
+        }
+        out
+    }
+}
+
+fn records(chunk: &[u8], size: usize) -> Vec<&[u8]> {
+    chunk.chunks_exact(size).collect()
+}
+
+fn word(record: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([record[at], record[at + 1]])
+}
+
+fn dword(record: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(record[at..at + 4].try_into().unwrap()) as usize
+}
+
+/// The zones of the bags from `from` to `to`, with the generators of the global
+/// zone, if there is one, in each of them. Zones without the `terminal` generator,
+/// which every zone but the global one ends with, are left out.
+fn bag_zones(
+    bags: &[&[u8]],
+    generators: &[&[u8]],
+    from: usize,
+    to: usize,
+    terminal: u16,
+) -> Vec<Generators> {
+    let mut global = Generators::new();
+    let mut zones = Vec::new();
+    for i in from..to.min(bags.len().saturating_sub(1)) {
+        let (first, last) = (word(bags[i], 0) as usize, word(bags[i + 1], 0) as usize);
+        let own = generators.get(first..last).unwrap_or(&[]);
+        let mut zone = global.clone();
+        for generator in own {
+            zone.insert(word(generator, 0), [generator[2], generator[3]]);
+        }
+        if own.iter().any(|g| word(g, 0) == terminal) {
+            zones.push(zone);
+        } else if i == from {
+            global = zone;
+        }
+    }
+    zones
+}
+
+fn amount(generators: &Generators, op: u16) -> Option<i16> {
+    generators.get(&op).map(|a| i16::from_le_bytes(*a))
+}
+
+fn range(generators: &Generators, op: u16) -> (u8, u8) {
+    generators.get(&op).map_or((0, 127), |a| (a[0], a[1]))
+}
+
+fn intersect(a: (u8, u8), b: (u8, u8)) -> Option<(u8, u8)> {
+    let range = (a.0.max(b.0), a.1.min(b.1));
+    (range.0 <= range.1).then_some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Generator = (u16, [u8; 2]);
+
+    fn signed(op: u16, amount: i16) -> Generator {
+        (op, amount.to_le_bytes())
+    }
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = [&id[..], &(body.len() as u32).to_le_bytes(), body].concat();
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[&kind[..], &chunks.concat()].concat())
+    }
+
+    /// Bags of `zones` and their generators, the bags ending with the one the
+    /// next list would start at.
+    fn bags(zones: &[Vec<Generator>]) -> (Vec<u8>, Vec<u8>) {
+        let (mut bags, mut generators) = (vec![], vec![]);
+        for zone in zones {
+            bags.extend((generators.len() as u16 / 4).to_le_bytes());
+            bags.extend([0, 0]);
+            for (op, amount) in zone {
+                generators.extend(op.to_le_bytes());
+                generators.extend(amount);
+            }
+        }
+        bags.extend((generators.len() as u16 / 4).to_le_bytes());
+        bags.extend([0, 0]);
+        (bags, generators)
+    }
+
+    /// An SF2 file with one preset of one preset zone, which has the `preset`
+    /// generators, playing one instrument of `zones`. Each sample is 100 samples
+    /// long and has the kind at its index in `kinds`.
+    fn font(preset: &[Generator], zones: &[Vec<Generator>], kinds: &[u16]) -> SoundFont {
+        let name = |name: &str| {
+            let mut bytes = name.as_bytes().to_vec();
+            bytes.resize(20, 0);
+            bytes
+        };
+        let mut phdr = vec![];
+        for (preset, bag) in [("Preset", 0u16), ("EOP", 1)] {
+            phdr.extend(name(preset));
+            phdr.extend([0, 0, 0, 0]);
+            phdr.extend(bag.to_le_bytes());
+            phdr.extend([0; 12]);
+        }
+        let preset = [preset, &[(INSTRUMENT, [0, 0])]].concat();
+        let (pbag, pgen) = bags(&[preset]);
+        let mut inst = vec![];
+        for (instrument, bag) in [("Instrument", 0), ("EOI", zones.len() as u16)] {
+            inst.extend(name(instrument));
+            inst.extend(bag.to_le_bytes());
+        }
+        let (ibag, igen) = bags(zones);
+        let mut shdr = vec![];
+        for (i, kind) in kinds.iter().enumerate() {
+            let start = i as u32 * 100;
+            shdr.extend(name("Sample"));
+            for at in [start, start + 100, start, start + 100, 22050] {
+                shdr.extend(at.to_le_bytes());
+            }
+            shdr.extend([60, 0, 0, 0]);
+            shdr.extend(kind.to_le_bytes());
+        }
+        shdr.extend(name("EOS"));
+        shdr.extend([0; 26]);
+        let smpl = vec![0; kinds.len() * 200];
+
+        let body = [
+            &b"sfbk"[..],
+            &list(b"sdta", &[chunk(b"smpl", &smpl)]),
+            &list(
+                b"pdta",
+                &[
+                    chunk(b"phdr", &phdr),
+                    chunk(b"pbag", &pbag),
+                    chunk(b"pgen", &pgen),
+                    chunk(b"inst", &inst),
+                    chunk(b"ibag", &ibag),
+                    chunk(b"igen", &igen),
+                    chunk(b"shdr", &shdr),
+                ],
+            ),
+        ]
+        .concat();
+        SoundFont::parse(&chunk(b"RIFF", &body)).unwrap()
+    }
+
+    #[test]
+    fn zones_are_chosen_by_key_and_velocity() {
+        let low = vec![(KEY_RANGE, [0, 63]), signed(SAMPLE_ID, 0)];
+        let high = vec![
+            (KEY_RANGE, [64, 127]),
+            (VELOCITY_RANGE, [0, 100]),
+            signed(SAMPLE_ID, 1),
+        ];
+        let font = font(&[], &[low, high], &[1, 1]);
+        let zones = &font.presets[font.preset(0, 0).unwrap()].zones;
+        let playing = |key, velocity| -> Vec<usize> {
+            zones
+                .iter()
+                .filter(|z| z.plays(key, velocity))
+                .map(|z| z.start)
+                .collect()
+        };
+        assert_eq!(playing(60, 127), [0]);
+        assert_eq!(playing(72, 100), [100]);
+        assert_eq!(playing(72, 101), []);
+        // a key range on the preset narrows the instrument's
+        let font = self::font(
+            &[(KEY_RANGE, [60, 70])],
+            &[vec![(KEY_RANGE, [0, 63]), signed(SAMPLE_ID, 0)]],
+            &[1],
+        );
+        assert_eq!(font.presets[0].zones[0].keys, (60, 63));
+    }
+
+    #[test]
+    fn preset_generators_are_added_to_the_instrument_ones() {
+        let zone = vec![
+            signed(ATTENUATION, 40),
+            signed(COARSE_TUNE, 2),
+            signed(SAMPLE_ID, 0),
+        ];
+        let font = font(
+            &[signed(ATTENUATION, 60), signed(COARSE_TUNE, 1)],
+            &[zone],
+            &[1],
+        );
+        let zone = &font.presets[0].zones[0];
+        // 100 centibels are 10 dB
+        assert!((zone.gain - 10f32.powf(-0.5)).abs() < 1e-6);
+        assert_eq!(zone.root, 57.0);
+    }
+
+    #[test]
+    fn right_channels_of_stereo_samples_are_skipped() {
+        // a left sample and the right one linked to it
+        let zones = [0, 1].map(|id| vec![signed(SAMPLE_ID, id)]);
+        let font = font(&[], &zones, &[4, 2]);
+        let starts: Vec<usize> = font.presets[0].zones.iter().map(|z| z.start).collect();
+        assert_eq!(starts, [0]);
+    }
+}
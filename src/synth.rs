@@ -0,0 +1,458 @@
+use std::{
+    f32::consts::{FRAC_1_SQRT_2, SQRT_2, TAU},
+    sync::Arc,
+};
+
+use crate::effects::{pan_gains, Effects, Processor};
+use crate::parser::data::Drum;
+use crate::random::Random;
+use crate::sample::Sample;
+use crate::soundfont::SoundFont;
+use crate::timeline::{Cursor, Timeline, Tone};
+
+/// Samples per second rendered unless the program sets another `samplerate`.
+pub const SAMPLE_RATE: u32 = 44100;
+const AMPLITUDE: f32 = 0.2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    Saw,
+    Noise,
+    /// A square wave that's high for the given fraction of its period.
+    Pulse(f32),
+}
+
+impl Waveform {
+    /// `width` is only used by pulse waves.
+    pub fn from_name(name: &str, width: f32) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sine" => Some(Self::Sine),
+            "square" => Some(Self::Square),
+            "triangle" => Some(Self::Triangle),
+            "saw" => Some(Self::Saw),
+            "noise" => Some(Self::Noise),
+            "pulse" => Some(Self::Pulse(width.clamp(0.0, 1.0))),
+            _ => None,
+        }
+    }
+
+    /// The value of the wave at `phase`, which goes from 0 to 1 over a period and
+    /// moves by `step` every sample. The corners of the waves are rounded over a
+    /// step (PolyBLEP) so that high notes don't alias.
+    fn sample(&self, phase: f32, step: f32, noise: &mut Random) -> f32 {
+        let step = step.min(0.5);
+        match self {
+            Self::Sine => (TAU * phase).sin(),
+            Self::Square => Self::Pulse(0.5).sample(phase, step, noise),
+            Self::Pulse(width) => {
+                let value = if phase < *width { 1.0 } else { -1.0 };
+                value + blep(phase, step) - blep((phase + 1.0 - width).fract(), step)
+            }
+            Self::Triangle => {
+                let value = 4.0 * (phase - 0.5).abs() - 1.0;
+                // the slope goes from 4 to -4 at the top and back at the bottom
+                value - 8.0 * step * (blamp(phase, step) - blamp((phase + 0.5).fract(), step))
+            }
+            Self::Saw => 2.0 * phase - 1.0 - blep(phase, step),
+            Self::Noise => noise.float() * 2.0 - 1.0,
+        }
+    }
+}
+
+/// What smooths a jump up by 2 at phase 0 over the step before and after it.
+fn blep(phase: f32, step: f32) -> f32 {
+    if phase < step {
+        let t = phase / step;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// The integral of `blep`, which smooths a corner at phase 0 whose slope goes up
+/// by 1 per step.
+fn blamp(phase: f32, step: f32) -> f32 {
+    if phase < step {
+        let t = phase / step - 1.0;
+        -t * t * t / 3.0
+    } else if phase > 1.0 - step {
+        let t = (phase - 1.0) / step + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// Times are in seconds.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    /// level held after the decay, from 0 to 1.
+    pub sustain: f32,
+    /// how long the note fades out after it's done, overlapping what comes after it.
+    pub release: f32,
+}
+
+impl Envelope {
+    /// Level at `time` into a note that's held for `held`.
+    pub fn level(&self, time: f32, held: f32) -> f32 {
+        if time < held {
+            self.held_level(time)
+        } else if time < held + self.release {
+            self.held_level(held) * (1.0 - (time - held) / self.release)
+        } else {
+            0.0
+        }
+    }
+
+    fn held_level(&self, time: f32) -> f32 {
+        if time < self.attack {
+            time / self.attack
+        } else if time < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (time - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Instrument {
+    Synth {
+        waveform: Waveform,
+        envelope: Envelope,
+    },
+    /// The built-in drum kit, keys pick a drum like in General MIDI.
+    Drums,
+    /// A recording played faster or slower for other keys than `root`. It's played
+    /// once until its end unless it's looped, then it repeats while it's held.
+    Sample {
+        sample: Arc<Sample>,
+        root: i32,
+        looped: bool,
+        envelope: Envelope,
+    },
+    /// A preset of a SoundFont, which has its own envelopes.
+    SoundFont { font: Arc<SoundFont>, preset: usize },
+}
+
+impl Instrument {
+    /// The samples of a tone at `freq` held for `held` seconds, including its release,
+    /// at `sample_rate` samples per second.
+    fn sound(
+        &self,
+        tone: &Tone,
+        freq: f32,
+        held: f32,
+        sample_rate: u32,
+        noise: &mut Random,
+    ) -> Vec<f32> {
+        let key = tone.key;
+        let rate = sample_rate as f32;
+        match self {
+            Self::Synth { waveform, envelope } => {
+                let samples = ((held + envelope.release) * rate) as usize;
+                let step = freq / rate;
+                (0..samples)
+                    .map(|i| {
+                        let time = i as f32 / rate;
+                        // f32 loses the phase over long notes
+                        let phase = (freq as f64 * i as f64 / rate as f64).fract() as f32;
+                        envelope.level(time, held) * waveform.sample(phase, step, noise)
+                    })
+                    .collect()
+            }
+            Self::Drums => Drum::from_key(key).map_or(vec![], |drum| drum_sound(drum, rate, noise)),
+            Self::Sample {
+                sample,
+                root,
+                looped,
+                envelope,
+            } => {
+                // samples of the recording per sample of the output
+                let step = 2f64.powf((key - root) as f64 / 12.0) * sample.rate as f64 / rate as f64;
+                let length = sample.data.len() as f64;
+                let (samples, held) = if *looped {
+                    (((held + envelope.release) * rate) as usize, held)
+                } else {
+                    ((length / step) as usize, f32::INFINITY)
+                };
+                (0..samples)
+                    .map(|i| {
+                        let position = i as f64 * step;
+                        let position = if *looped { position % length } else { position };
+                        envelope.level(i as f32 / rate, held) * sample.at(position, step)
+                    })
+                    .collect()
+            }
+            Self::SoundFont { font, preset } => {
+                let mut out: Vec<f32> = vec![];
+                let zones = font.presets[*preset].zones.iter();
+                for zone in zones.filter(|z| z.plays(key, tone.velocity)) {
+                    let sound = zone.sound(font, key, held, rate);
+                    if out.len() < sound.len() {
+                        out.resize(sound.len(), 0.0);
+                    }
+                    for (sample, value) in out.iter_mut().zip(sound) {
+                        *sample += value;
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+/// A drum hit, which rings out on its own however long it's held.
+fn drum_sound(drum: Drum, rate: f32, noise: &mut Random) -> Vec<f32> {
+    // seconds it takes to fade to about a third
+    let decay = match drum {
+        Drum::Kick => 0.15,
+        Drum::Rim => 0.02,
+        Drum::Snare => 0.1,
+        Drum::Clap => 0.08,
+        Drum::Hat => 0.03,
+        Drum::OpenHat => 0.25,
+        Drum::Tom => 0.2,
+        Drum::Crash => 0.8,
+        Drum::Ride => 0.5,
+        Drum::Cowbell => 0.12,
+    };
+    let samples = (decay * 6.0 * rate) as usize;
+    let mut out = Vec::with_capacity(samples);
+    let mut last = 0.0;
+    for i in 0..samples {
+        let time = i as f32 / rate;
+        let white = noise.float() * 2.0 - 1.0;
+        // cymbals are noise without its low end
+        let bright = white - last;
+        last = white;
+        let value = match drum {
+            Drum::Kick => sweep(time, 50.0, 100.0, 0.04),
+            Drum::Tom => sweep(time, 90.0, 60.0, 0.05),
+            Drum::Snare => 0.5 * (TAU * 185.0 * time).sin() + 0.7 * white,
+            Drum::Rim => 0.6 * (TAU * 1700.0 * time).sin() + 0.4 * white,
+            // a few quick claps before the last one
+            Drum::Clap if time < 0.03 => white * (1.0 - (time / 0.01).fract()),
+            Drum::Clap => white,
+            Drum::Hat | Drum::OpenHat | Drum::Crash | Drum::Ride => 0.7 * bright,
+            Drum::Cowbell => {
+                let square = |freq: f32| {
+                    if (freq * time).fract() < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                };
+                0.3 * (square(540.0) + square(800.0))
+            }
+        };
+        out.push(value * (-time / decay).exp());
+    }
+    out
+}
+
+/// A sine whose frequency falls from `low + high` to `low` over about `time` seconds.
+fn sweep(t: f32, low: f32, high: f32, time: f32) -> f32 {
+    (TAU * (low * t + high * time * (1.0 - (-t / time).exp()))).sin()
+}
+
+/// Mixes every voice of the timeline into samples of `timeline.channels` channels,
+/// interleaved for stereo.
+pub fn render(timeline: &Timeline) -> Vec<f32> {
+    Renderer::new(timeline).finish(timeline)
+}
+
+/// Mixes a timeline a bit at a time, which lets it be written out while the
+/// program is still playing it. Everything rendered is final, so tones that start
+/// before what's been rendered are cut.
+pub struct Renderer {
+    sample_rate: u32,
+    channels: u16,
+    /// Frames rendered so far.
+    position: usize,
+    /// Where each voice's tones are up to.
+    cursors: Vec<Cursor>,
+    /// Chords with different effects are mixed apart so that each gets its own.
+    groups: Vec<Group>,
+    master: [Processor; 2],
+    /// Frames the master effects ring out for.
+    tail: usize,
+}
+
+/// The tones of a voice played with the same effects.
+struct Group {
+    voice: usize,
+    effects: Effects,
+    processor: Processor,
+    /// What's left to render, from `Renderer::position` on.
+    pending: Vec<f32>,
+    /// Frame after which the group is silent, its effects included.
+    end: usize,
+}
+
+impl Renderer {
+    pub fn new(timeline: &Timeline) -> Self {
+        let rate = timeline.sample_rate as f32;
+        let master = &timeline.master;
+        Self {
+            sample_rate: timeline.sample_rate,
+            channels: timeline.channels,
+            position: 0,
+            cursors: vec![],
+            groups: vec![],
+            master: [master.processor(rate), master.processor(rate)],
+            tail: (master.tail() * rate) as usize,
+        }
+    }
+
+    /// Frames rendered so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Renders the timeline up to frame `until`.
+    pub fn render(&mut self, timeline: &Timeline, until: usize) -> Vec<f32> {
+        self.add_tones(timeline);
+        let frames = until.saturating_sub(self.position);
+        let mut left = vec![0.0; frames];
+        let mut right = vec![0.0; frames];
+        let mut buffer = vec![0.0; frames];
+        for group in self.groups.iter_mut() {
+            let pending = group.pending.len().min(frames);
+            buffer.fill(0.0);
+            for (sample, value) in buffer.iter_mut().zip(group.pending.drain(..pending)) {
+                *sample = value;
+            }
+            group.processor.process(&mut buffer);
+            // groups are dropped once they're over, which is also where they end when
+            // everything is rendered at once
+            let end = group.end.saturating_sub(self.position).min(frames);
+            buffer[end..].fill(0.0);
+            let (l, r) = pan_gains(group.effects.pan);
+            mix(&mut left, 0, buffer.iter().map(|s| s * l));
+            mix(&mut right, 0, buffer.iter().map(|s| s * r));
+        }
+        self.position += frames;
+        let position = self.position;
+        self.groups.retain(|g| g.end > position);
+
+        self.master[0].process(&mut left);
+        self.master[1].process(&mut right);
+        // balance, which leaves both channels as they are in the center and only
+        // turns down the one it's away from, so that it doesn't clip
+        let (l, r) = pan_gains(timeline.master.pan);
+        let (l, r) = ((l * SQRT_2).min(1.0), (r * SQRT_2).min(1.0));
+        if self.channels == 1 {
+            // undoes the centered pan of the voices
+            let gain = l.min(r) * FRAC_1_SQRT_2;
+            return left
+                .iter()
+                .zip(right)
+                .map(|(a, b)| (a + b) * gain)
+                .collect();
+        }
+        left.iter()
+            .zip(right)
+            .flat_map(|(a, b)| [a * l, b * r])
+            .collect()
+    }
+
+    /// Renders the rest of the timeline, until every voice and effect has stopped.
+    pub fn finish(&mut self, timeline: &Timeline) -> Vec<f32> {
+        let end = self.end(timeline);
+        self.render(timeline, end)
+    }
+
+    /// The frame where every voice and effect of the timeline has stopped.
+    pub fn end(&mut self, timeline: &Timeline) -> usize {
+        self.add_tones(timeline);
+        let rate = self.sample_rate as f32;
+        let length = (timeline.seconds(timeline.length()) * rate).ceil() as usize;
+        let end = self.groups.iter().map(|g| g.end).fold(length, usize::max);
+        end + self.tail
+    }
+
+    /// Lets go of what's been rendered: the events whose tones have all been mixed
+    /// in, and the spawned voices that have stopped sounding and aren't `playing`
+    /// any more. Gives back the voices that were dropped, see `Timeline::forget`.
+    pub fn forget(&mut self, timeline: &mut Timeline, playing: &[usize]) -> Vec<usize> {
+        let groups = &self.groups;
+        let dropped = timeline.forget(&mut self.cursors, |v| {
+            !playing.contains(&v) && groups.iter().all(|g| g.voice != v)
+        });
+        for group in self.groups.iter_mut() {
+            group.voice -= dropped.iter().filter(|v| **v < group.voice).count();
+        }
+        dropped
+    }
+
+    /// Mixes the tones the timeline has gained since the last time into their groups.
+    fn add_tones(&mut self, timeline: &Timeline) {
+        let rate = self.sample_rate as f32;
+        while self.cursors.len() < timeline.voices.len() {
+            self.cursors.push(timeline.cursor(self.cursors.len()));
+        }
+        for (v, cursor) in self.cursors.iter_mut().enumerate() {
+            while let Some((event, tone, (start, end))) = timeline.next_tone(cursor) {
+                let held = timeline.seconds(end - start);
+                let start = (timeline.seconds(start) * rate) as usize;
+                let freq = timeline.frequency(tone.key);
+                // seeded by the tone so that noise sounds the same on every render
+                let mut noise = Random::new(start as u32 ^ tone.key as u32);
+                let sound = event
+                    .instrument
+                    .sound(tone, freq, held, self.sample_rate, &mut noise);
+                let gain = AMPLITUDE * tone.velocity as f32 / 127.0;
+
+                let group = self
+                    .groups
+                    .iter()
+                    .position(|g| g.voice == v && g.effects == event.effects);
+                let group = match group {
+                    Some(i) => &mut self.groups[i],
+                    None => {
+                        // in the order of their voices, so that the groups are mixed in
+                        // the same order however the timeline is rendered
+                        let i = self.groups.partition_point(|g| g.voice <= v);
+                        self.groups.insert(
+                            i,
+                            Group {
+                                voice: v,
+                                effects: event.effects.clone(),
+                                processor: event.effects.processor(rate),
+                                pending: vec![],
+                                end: 0,
+                            },
+                        );
+                        &mut self.groups[i]
+                    }
+                };
+                let tail = (event.effects.tail() * rate) as usize;
+                group.end = group.end.max(start + sound.len() + tail);
+                // what's already been rendered can't be changed
+                let skip = self.position.saturating_sub(start);
+                let start = start.saturating_sub(self.position);
+                let sound = sound.into_iter().skip(skip).map(|s| s * gain);
+                mix(&mut group.pending, start, sound);
+            }
+        }
+    }
+}
+
+/// Adds `sound` to `out` from `start` on, making `out` longer if it has to.
+fn mix(out: &mut Vec<f32>, start: usize, sound: impl ExactSizeIterator<Item = f32>) {
+    if out.len() < start + sound.len() {
+        out.resize(start + sound.len(), 0.0);
+    }
+    for (sample, value) in out[start..].iter_mut().zip(sound) {
+        *sample += value;
+    }
+}
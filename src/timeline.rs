@@ -0,0 +1,352 @@
+use crate::effects::Effects;
+use crate::random::Random;
+use crate::synth::{Instrument, SAMPLE_RATE};
+
+/// A chord placed on a voice.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// When the chord starts, in whole notes.
+    pub start: f32,
+    /// How long the chord lasts, in whole notes.
+    pub duration: f32,
+    pub tones: Vec<Tone>,
+    pub instrument: Instrument,
+    pub timing: Timing,
+    pub lyric: Option<String>,
+    pub effects: Effects,
+}
+
+/// How far the notes of a chord are moved from where they were written.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// Fraction of a beat the first of its eighths takes, 0.5 being straight.
+    pub swing: f32,
+    /// Seconds the notes are moved by at most, earlier or later.
+    pub humanize: f32,
+}
+
+impl Timing {
+    /// Moves the off-beat eighths of `time`, in whole notes, so that they're swung.
+    fn swing(&self, time: f32) -> f32 {
+        let beats = time * 4.0;
+        let beat = beats.floor();
+        let x = beats - beat;
+        let x = if x < 0.5 {
+            x * self.swing / 0.5
+        } else {
+            self.swing + (x - 0.5) * (1.0 - self.swing) / 0.5
+        };
+        (beat + x) / 4.0
+    }
+}
+
+/// A single note of a chord.
+#[derive(Debug, Clone)]
+pub struct Tone {
+    /// MIDI key, A4 being 69.
+    pub key: i32,
+    /// MIDI velocity, from 1 to 127.
+    pub velocity: u8,
+    /// How long the note sounds for, in whole notes.
+    pub length: f32,
+    /// When the note starts after the start of its chord, in whole notes.
+    pub offset: f32,
+}
+
+/// The order an arpeggio plays the notes of a chord in.
+#[derive(Debug, Clone)]
+pub enum Arpeggio {
+    Up,
+    Down,
+    UpDown,
+    Random,
+}
+
+impl Arpeggio {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "up" => Some(Self::Up),
+            "down" => Some(Self::Down),
+            "updown" => Some(Self::UpDown),
+            "random" => Some(Self::Random),
+            _ => None,
+        }
+    }
+
+    /// Plays `notes`, given as (key, velocity, fraction of its duration it sounds for),
+    /// one after another every `rate` whole notes until `duration` is over.
+    pub fn tones(
+        &self,
+        notes: &[(i32, u8, f32)],
+        duration: f32,
+        rate: f32,
+        random: &mut Random,
+    ) -> Vec<Tone> {
+        let mut notes = notes.to_vec();
+        notes.sort_by_key(|(key, _, _)| *key);
+        let order: Vec<usize> = match self {
+            Self::Up => (0..notes.len()).collect(),
+            Self::Down => (0..notes.len()).rev().collect(),
+            // the top and bottom notes aren't repeated
+            Self::UpDown => (0..notes.len()).chain((1..notes.len() - 1).rev()).collect(),
+            Self::Random => vec![],
+        };
+        let mut tones = vec![];
+        let mut offset = 0.0;
+        let mut step = 0;
+        while offset < duration {
+            let index = match self {
+                Self::Random => random.range(0, notes.len() as i64 - 1) as usize,
+                _ => order[step % order.len()],
+            };
+            let (key, velocity, legato) = notes[index];
+            let length = rate.min(duration - offset) * legato;
+            tones.push(Tone {
+                key,
+                velocity,
+                length,
+                offset,
+            });
+            step += 1;
+            offset = step as f32 * rate;
+        }
+        tones
+    }
+}
+
+/// The loudness of a voice, which changes from `from` to `to` between the times
+/// `start` and `end` for crescendos and diminuendos.
+#[derive(Debug, Clone)]
+struct Dynamic {
+    from: f32,
+    to: f32,
+    start: f32,
+    end: f32,
+}
+
+/// A sequence of chords that plays alongside every other voice.
+#[derive(Debug)]
+pub struct Voice {
+    pub name: String,
+    /// Where the next chord is placed, in whole notes.
+    pub time: f32,
+    pub events: Vec<Event>,
+    /// key of the last note played, which intervals are relative to.
+    pub last_key: Option<i32>,
+    /// Names of points of the voice and when they are, in whole notes.
+    pub markers: Vec<(f32, String)>,
+    dynamic: Dynamic,
+}
+
+impl Voice {
+    /// Voices start at mezzo forte.
+    pub const VELOCITY: u8 = 80;
+
+    pub fn new(name: &str, time: f32) -> Self {
+        let name = name.into();
+        let events = Vec::new();
+        let velocity = Self::VELOCITY as f32;
+        let dynamic = Dynamic {
+            from: velocity,
+            to: velocity,
+            start: time,
+            end: time,
+        };
+        Self {
+            name,
+            time,
+            events,
+            last_key: None,
+            markers: Vec::new(),
+            dynamic,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        tones: Vec<Tone>,
+        duration: f32,
+        instrument: Instrument,
+        timing: Timing,
+        lyric: Option<String>,
+        effects: Effects,
+    ) {
+        let start = self.time;
+        self.events.push(Event {
+            start,
+            duration,
+            tones,
+            instrument,
+            timing,
+            lyric,
+            effects,
+        });
+        self.time += duration;
+    }
+
+    /// The velocity at the voice's current time.
+    pub fn velocity(&self) -> u8 {
+        let Dynamic {
+            from,
+            to,
+            start,
+            end,
+        } = self.dynamic;
+        let velocity = if self.time >= end {
+            to
+        } else {
+            from + (to - from) * (self.time - start) / (end - start)
+        };
+        velocity.round().clamp(1.0, 127.0) as u8
+    }
+
+    /// Changes the velocity gradually over `duration` whole notes, or at once if it's 0.
+    pub fn set_velocity(&mut self, velocity: u8, duration: f32) {
+        self.dynamic = Dynamic {
+            from: self.velocity() as f32,
+            to: velocity as f32,
+            start: self.time,
+            end: self.time + duration,
+        };
+    }
+}
+
+/// Where `Timeline::next_tone` is in the tones of a voice, which lets them be
+/// scheduled as they're played.
+#[derive(Debug)]
+pub struct Cursor {
+    voice: usize,
+    event: usize,
+    tone: usize,
+    random: Random,
+}
+
+/// Everything a program played, which the exporters turn into audio or MIDI.
+#[derive(Debug)]
+pub struct Timeline {
+    pub bpm: f32,
+    /// Frequency of A4.
+    pub pitch: f32,
+    pub voices: Vec<Voice>,
+    /// What humanized notes are moved by depends on it.
+    pub seed: u32,
+    /// Effects applied to every voice mixed together.
+    pub master: Effects,
+    /// 1 for mono or 2 for stereo.
+    pub channels: u16,
+    /// Samples per second of the rendered audio.
+    pub sample_rate: u32,
+    /// Voices dropped by `forget`, which the voices after them are numbered after.
+    forgotten: usize,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        let bpm = 60.0;
+        let pitch = 440.0;
+        let voices = vec![Voice::new("main", 0.0)];
+        Self {
+            bpm,
+            pitch,
+            voices,
+            seed: 1,
+            master: Effects::default(),
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            forgotten: 0,
+        }
+    }
+
+    /// Converts whole notes into seconds, a beat being a quarter note.
+    pub fn seconds(&self, duration: f32) -> f32 {
+        duration * 4.0 * 60.0 / self.bpm
+    }
+
+    /// When each tone of a voice starts and stops, in whole notes, once swing and
+    /// humanize are applied. The order matches the voice's events and their tones.
+    pub fn schedule(&self, voice: usize) -> Vec<(f32, f32)> {
+        let mut cursor = self.cursor(voice);
+        let mut out = vec![];
+        while let Some((_, _, times)) = self.next_tone(&mut cursor) {
+            out.push(times);
+        }
+        out
+    }
+
+    /// A cursor at the first tone of a voice.
+    pub fn cursor(&self, voice: usize) -> Cursor {
+        Cursor {
+            voice,
+            event: 0,
+            tone: 0,
+            random: Random::new(self.seed ^ (self.forgotten + voice) as u32),
+        }
+    }
+
+    /// Drops the events that `cursors` have gone past, so that a program that loops
+    /// forever can be streamed without running out of memory, along with the voices
+    /// after the first one that have nothing left to play, don't go on for longer
+    /// than another one, and are `silent`. The cursors are moved to match. Gives back the
+    /// voices that were dropped, from the last one on.
+    pub fn forget(
+        &mut self,
+        cursors: &mut Vec<Cursor>,
+        silent: impl Fn(usize) -> bool,
+    ) -> Vec<usize> {
+        for cursor in cursors.iter_mut() {
+            self.voices[cursor.voice].events.drain(..cursor.event);
+            cursor.event = 0;
+        }
+        let mut dropped = vec![];
+        for v in (1..cursors.len()).rev() {
+            let voice = &self.voices[v];
+            // the length of the timeline stays the same
+            let shorter = (self.voices.iter().enumerate())
+                .any(|(u, other)| u != v && voice.time <= other.time);
+            if voice.events.is_empty() && shorter && silent(v) {
+                self.voices.remove(v);
+                cursors.remove(v);
+                dropped.push(v);
+            }
+        }
+        for (v, cursor) in cursors.iter_mut().enumerate() {
+            cursor.voice = v;
+        }
+        self.forgotten += dropped.len();
+        dropped
+    }
+
+    /// The tone at `cursor` with its event and when it starts and stops, like in
+    /// `schedule`, then moves the cursor to the next one. `None` if the voice hasn't
+    /// played it yet.
+    pub fn next_tone(&self, cursor: &mut Cursor) -> Option<(&Event, &Tone, (f32, f32))> {
+        let events = &self.voices[cursor.voice].events;
+        // events can be empty, like drum hits of nothing
+        while events.get(cursor.event)?.tones.len() <= cursor.tone {
+            cursor.event += 1;
+            cursor.tone = 0;
+        }
+        let event = &events[cursor.event];
+        let tone = &event.tones[cursor.tone];
+        cursor.tone += 1;
+        let timing = event.timing;
+        let start = event.start + tone.offset;
+        let end = start + tone.length;
+        // always drawn so that humanizing one chord doesn't change the others, and at
+        // most a beat so that notes aren't moved before what's been streamed
+        let humanize = (timing.humanize * self.bpm / 240.0).min(0.25);
+        let jitter = (cursor.random.float() * 2.0 - 1.0) * humanize;
+        let start = timing.swing(start) + jitter;
+        let end = timing.swing(end) + jitter;
+        Some((event, tone, (start.max(0.0), end.max(0.0))))
+    }
+
+    /// When the last voice stops, in whole notes.
+    pub fn length(&self) -> f32 {
+        self.voices.iter().map(|v| v.time).fold(0.0, f32::max)
+    }
+
+    pub fn frequency(&self, key: i32) -> f32 {
+        self.pitch * 2f32.powf((key - 69) as f32 / 12.0)
+    }
+}